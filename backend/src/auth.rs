@@ -0,0 +1,264 @@
+use std::{collections::HashMap, marker::PhantomData, sync::{Arc, Mutex}};
+
+use argon2::{password_hash::{rand_core::{OsRng, RngCore}, PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Argon2};
+use axum::{extract::{FromRequestParts, Query, Request, State}, http::{header, request::Parts, HeaderMap, StatusCode}, middleware::Next, response::{IntoResponse, Response}, Json};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+use crate::{models::{Account, AppState, Role}, store::ChatStore};
+
+/// Name of the cookie holding the session token.
+pub const SESSION_COOKIE: &str = "session";
+
+/// A logged in admin, stored in the request extensions by `require_session`.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub username: String,
+    pub role: Role,
+    pub expires_at: u64,
+}
+
+/// Active sessions keyed by token. Kept in memory only, so a restart logs everyone out.
+pub struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+    /// How long a login stays valid.
+    ttl_ms: u64,
+}
+
+impl Sessions {
+    pub fn new(ttl_ms: u64) -> Self {
+        Self {
+            sessions: Mutex::new(HashMap::new()),
+            ttl_ms,
+        }
+    }
+
+    /// Starts a session for `username` and returns its token.
+    pub fn create(&self, username: &str, role: Role) -> String {
+        let token = generate_token();
+        let session = Session {
+            username: username.to_string(),
+            role,
+            expires_at: now_ms() + self.ttl_ms,
+        };
+
+        let mut sessions = self.sessions.lock().unwrap();
+        let now = now_ms();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(token.clone(), session);
+        token
+    }
+
+    pub fn get(&self, token: &str) -> Option<Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(token) {
+            Some(session) if session.expires_at > now_ms() => Some(session.clone()),
+            Some(_) => {
+                sessions.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn remove(&self, token: &str) {
+        self.sessions.lock().unwrap().remove(token);
+    }
+
+    /// Ends every session of `username`, e.g. after a password or role change.
+    pub fn remove_user(&self, username: &str) {
+        self.sessions.lock().unwrap().retain(|_, session| session.username != username);
+    }
+
+    /// The `Set-Cookie` value for a session created by `create`.
+    pub fn cookie(&self, token: &str) -> String {
+        format!("{}={}; Path=/; HttpOnly; SameSite=Strict; Max-Age={}", SESSION_COOKIE, token, self.ttl_ms / 1000)
+    }
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!("Failed to hash password: {}", e))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// A random 256-bit token, hex encoded.
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now_ms() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+/// Finds the session token in the `Authorization: Bearer` header, the session
+/// cookie or, for websocket clients that cannot set headers, the `token` query parameter.
+pub fn request_token(headers: &HeaderMap, query_token: Option<&str>) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let cookie = || headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .find_map(|pair| pair.trim().strip_prefix(SESSION_COOKIE)?.strip_prefix('='));
+
+    bearer.or_else(cookie).or(query_token).map(str::to_string)
+}
+
+/// Whether the request asks to open a websocket.
+fn is_websocket_upgrade(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Rejects requests without a valid admin session.
+///
+/// Only websocket upgrades may carry the token in the query; REST calls need
+/// the cookie or the header, so tokens stay out of URLs, logs and referrers.
+pub async fn require_session(State(state): State<Arc<AppState>>, mut request: Request, next: Next) -> Response {
+    let query_token = is_websocket_upgrade(request.headers())
+        .then(|| Query::<HashMap<String, String>>::try_from_uri(request.uri()).ok())
+        .flatten()
+        .and_then(|Query(mut params)| params.remove("token"));
+    let session = request_token(request.headers(), query_token.as_deref())
+        .and_then(|token| state.sessions.get(&token));
+
+    match session {
+        Some(session) => {
+            request.extensions_mut().insert(session);
+            next.run(request).await
+        }
+        None => (
+            StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Not logged in"
+            })),
+        ).into_response(),
+    }
+}
+
+/// The least role a handler requires, used as the parameter of `Authorized`.
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct ViewerRole;
+pub struct ModeratorRole;
+pub struct OwnerRole;
+
+impl RequiredRole for ViewerRole {
+    const ROLE: Role = Role::Viewer;
+}
+
+impl RequiredRole for ModeratorRole {
+    const ROLE: Role = Role::Moderator;
+}
+
+impl RequiredRole for OwnerRole {
+    const ROLE: Role = Role::Owner;
+}
+
+/// Extracts the caller's session, rejecting the request with 403 unless the
+/// caller has at least role `R`. Relies on `require_session` having run.
+pub struct Authorized<R>(pub Session, pub PhantomData<R>);
+
+impl<R: RequiredRole, S: Send + Sync> FromRequestParts<S> for Authorized<R> {
+    type Rejection = (StatusCode, Json<serde_json::Value>);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<Session>() {
+            Some(session) if session.role >= R::ROLE => Ok(Authorized(session.clone(), PhantomData)),
+            Some(session) => Err((
+                StatusCode::FORBIDDEN,
+                Json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Requires the {} role, {} is a {}", R::ROLE.as_str(), session.username, session.role.as_str())
+                })),
+            )),
+            None => Err((
+                StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({
+                    "status": "error",
+                    "message": "Not logged in"
+                })),
+            )),
+        }
+    }
+}
+
+/// Whether the request may open the read-only overlay socket: either it carries
+/// the overlay token or it belongs to a logged in admin.
+pub fn is_overlay_allowed(state: &AppState, headers: &HeaderMap, query_token: Option<&str>) -> bool {
+    request_token(headers, query_token).is_some_and(|token| {
+        let overlay_token = state.overlay_token.read().unwrap();
+        bool::from(token.as_bytes().ct_eq(overlay_token.as_bytes())) || state.sessions.get(&token).is_some()
+    })
+}
+
+pub fn expired_session_cookie() -> String {
+    format!("{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0", SESSION_COOKIE)
+}
+
+/// Settings key of the overlay token.
+pub const OVERLAY_TOKEN_SETTING: &str = "overlay_token";
+
+/// Makes sure an admin account and an overlay token exist, returning the token.
+///
+/// With `admin_password` the `admin` owner account is created if it does not
+/// exist. An existing one is only changed with `reset_admin`, which resets its
+/// password and restores its role to owner. Without a password, a first start
+/// creates `admin` with a random password that is logged once.
+pub fn bootstrap(store: &dyn ChatStore, admin_password: Option<&str>, reset_admin: bool) -> anyhow::Result<String> {
+    let existing = store.get_account("admin")?;
+    let password = match (admin_password, &existing) {
+        (Some(password), None) => Some(password.to_string()),
+        (Some(password), Some(_)) if reset_admin => {
+            warn!("Reset the password and role of account admin");
+            Some(password.to_string())
+        }
+        (Some(_), Some(_)) => None,
+        (None, _) if store.count_accounts(None)? == 0 => {
+            let password = generate_token();
+            warn!("Created account admin with password {}; change it after logging in", password);
+            Some(password)
+        }
+        (None, _) => None,
+    };
+
+    if let Some(password) = password {
+        let created_at = existing
+            .map(|account| account.created_at)
+            .unwrap_or_else(now_ms);
+        store.save_account(&Account {
+            username: "admin".to_string(),
+            password_hash: hash_password(&password)?,
+            role: Role::Owner,
+            created_at,
+        })?;
+    }
+
+    match store.get_setting(OVERLAY_TOKEN_SETTING)? {
+        Some(token) => Ok(token),
+        None => {
+            let token = generate_token();
+            store.set_setting(OVERLAY_TOKEN_SETTING, &token)?;
+            Ok(token)
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::{collections::HashSet, sync::{atomic::Ordering, Arc, Mutex}};
+
+use crate::{
+    auth::Session,
+    models::{AppState, ChatMessage, MessageId, MessageStatus, Role},
+    protocol::{AdminCommand, AdminEvent, AdminRequest, BulkAction, ChannelKey, ClientEvent},
+    store::StatusChange,
+    utils,
+};
+
+/// Channels an admin socket wants message events for. `None` means all channels.
+pub type Subscription = Arc<Mutex<Option<HashSet<ChannelKey>>>>;
+
+/// Whether an event should be forwarded to a socket with the given subscription.
+pub fn is_subscribed(subscription: &Subscription, event: &AdminEvent) -> bool {
+    match event {
+        AdminEvent::Message(chat_message) | AdminEvent::MessageUpdated(chat_message) => {
+            is_subscribed_to(subscription, chat_message)
+        }
+        _ => true,
+    }
+}
+
+/// Whether a message belongs to one of the channels in the subscription.
+pub fn is_subscribed_to(subscription: &Subscription, chat_message: &ChatMessage) -> bool {
+    match &*subscription.lock().unwrap() {
+        Some(channels) => channels.contains(&ChannelKey {
+            platform: chat_message.platform.clone(),
+            channel: chat_message.channel.clone(),
+        }),
+        None => true,
+    }
+}
+
+/// Runs a command from the admin socket and builds the reply for it.
+/// Viewers may only change their own subscription; every other command needs a moderator.
+pub fn handle_command(state: &Arc<AppState>, session: &Session, request: AdminRequest, subscription: &Subscription) -> AdminEvent {
+    let request_id = request.request_id;
+
+    if !matches!(request.command, AdminCommand::Subscribe { .. }) && session.role < Role::Moderator {
+        return AdminEvent::Error {
+            request_id,
+            message: format!("Requires the moderator role, {} is a {}", session.username, session.role.as_str()),
+        };
+    }
+
+    let moderator = session.username.as_str();
+    let result = match request.command {
+        AdminCommand::Publish { id } => set_status(state, BulkAction::Publish, id, moderator).map(|_| ("publish", vec![id], vec![])),
+        AdminCommand::Reject { id } => set_status(state, BulkAction::Reject, id, moderator).map(|_| ("reject", vec![id], vec![])),
+        AdminCommand::Unpublish { id } => set_status(state, BulkAction::Unpublish, id, moderator).map(|_| ("unpublish", vec![id], vec![])),
+        AdminCommand::Edit { id, content } => validate_edit(content.as_deref())
+            .and_then(|_| edit(state, id, content.as_deref(), moderator)?.ok_or_else(|| anyhow::anyhow!("Message {} not found", id)))
+            .map(|_| ("edit", vec![id], vec![])),
+        AdminCommand::Pin { id } => pin(state, id).map(|_| ("pin", vec![id], vec![])),
+        AdminCommand::Unpin => Ok(("unpin", unpin(state).into_iter().collect(), vec![])),
+        AdminCommand::ClearScreen => {
+            clear_screen(state);
+            Ok(("clear_screen", vec![], vec![]))
+        }
+        AdminCommand::Bulk { action, ids } => {
+            let (succeeded, failed): (Vec<MessageId>, Vec<MessageId>) = ids.into_iter()
+                .partition(|&id| set_status(state, action, id, moderator).is_ok());
+            Ok(("bulk", succeeded, failed))
+        }
+        AdminCommand::Subscribe { channels } => {
+            *subscription.lock().unwrap() = if channels.is_empty() {
+                None
+            } else {
+                Some(channels.into_iter().collect())
+            };
+            Ok(("subscribe", vec![], vec![]))
+        }
+    };
+
+    match result {
+        Ok((command, ids, failed)) => AdminEvent::Ack {
+            request_id,
+            command: command.to_string(),
+            ids,
+            failed,
+        },
+        Err(e) => AdminEvent::Error {
+            request_id,
+            message: e.to_string(),
+        },
+    }
+}
+
+fn set_status(state: &AppState, action: BulkAction, id: MessageId, moderator: &str) -> anyhow::Result<ChatMessage> {
+    let _order = state.event_order.lock().unwrap();
+
+    let (result, done) = match action {
+        BulkAction::Publish => (utils::publish_message(state, id, moderator), "published"),
+        BulkAction::Reject => (utils::reject_message(state, id, moderator), "rejected"),
+        BulkAction::Unpublish => (utils::unpublish_message(state, id, moderator), "unpublished"),
+    };
+
+    match result? {
+        StatusChange::Changed { message, .. } => Ok(*message),
+        StatusChange::NotFound => Err(anyhow::anyhow!("Message {} not found", id)),
+        StatusChange::NotAllowed(current) => Err(anyhow::anyhow!("Message {} is {} and cannot be {}", id, current.as_str(), done)),
+    }
+}
+
+fn pin(state: &AppState, id: MessageId) -> anyhow::Result<()> {
+    // Held until the pin is broadcast so overlays connecting meanwhile see a consistent state.
+    let _order = state.event_order.lock().unwrap();
+    let chat_message = state.store.get_message(id)?
+        .ok_or_else(|| anyhow::anyhow!("Message {} not found", id))?;
+
+    if chat_message.status != MessageStatus::Published {
+        return Err(anyhow::anyhow!("Message {} is not published", id));
+    }
+
+    unpin(state);
+    let chat_message = chat_message.for_overlay();
+    *state.pinned_message.lock().unwrap() = Some(chat_message.clone());
+    let _ = state.client_sender.send(ClientEvent::MessagePinned(chat_message));
+    Ok(())
+}
+
+/// Refuses edited text that would leave nothing to show. `None` restores the
+/// original instead.
+pub fn validate_edit(content: Option<&str>) -> anyhow::Result<()> {
+    if content.is_some_and(|content| content.trim().is_empty()) {
+        return Err(anyhow::anyhow!("Edited content must not be empty"));
+    }
+    Ok(())
+}
+
+/// Edits a message and keeps the pinned copy in sync with it. Returns `None`
+/// if no message has that id.
+pub fn edit(state: &AppState, id: MessageId, content: Option<&str>, moderator: &str) -> anyhow::Result<Option<ChatMessage>> {
+    let _order = state.event_order.lock().unwrap();
+    let Some(chat_message) = utils::edit_message(state, id, content, moderator)? else {
+        return Ok(None);
+    };
+
+    let mut pinned = state.pinned_message.lock().unwrap();
+    if pinned.as_ref().is_some_and(|pinned| pinned.id == chat_message.id) {
+        *pinned = Some(chat_message.for_overlay());
+    }
+    Ok(Some(chat_message))
+}
+
+fn clear_screen(state: &AppState) {
+    // Held so overlays connecting meanwhile either get the old backlog and the
+    // clear event, or neither.
+    let _order = state.event_order.lock().unwrap();
+    unpin(state);
+    state.screen_cleared_at.store(chrono::Utc::now().timestamp_millis() as u64, Ordering::Relaxed);
+    let _ = state.client_sender.send(ClientEvent::ScreenCleared);
+}
+
+/// Removes the pinned message, returning its id if one was pinned.
+fn unpin(state: &AppState) -> Option<MessageId> {
+    let pinned = state.pinned_message.lock().unwrap().take()?;
+    let _ = state.client_sender.send(ClientEvent::MessageUnpinned { id: pinned.id });
+    Some(pinned.id)
+}
@@ -0,0 +1,334 @@
+use std::time::Duration;
+
+use clap::Parser;
+use tracing::{info, warn};
+
+use crate::models::{Args, MessageFilter};
+
+/// Settings read from the `--config` TOML file. Every section and key is
+/// optional; missing ones keep their defaults.
+///
+/// Values are applied in this order, later ones winning: defaults, the config
+/// file, `CHAT_*` environment variables, command line flags.
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub retention: RetentionConfig,
+    pub listeners: ListenerConfig,
+    pub filters: FilterConfig,
+    pub auth: AuthConfig,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Directory the admin frontend is served from.
+    pub static_dir: String,
+    /// How many events each websocket may fall behind before it has to resync.
+    pub broadcast_capacity: usize,
+    /// Origins allowed to call the API from another site.
+    pub allowed_origins: Vec<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 3000,
+            static_dir: "static".to_string(),
+            broadcast_capacity: 1000,
+            allowed_origins: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub backend: StoreBackend,
+    /// Database file, unused by the memory backend.
+    pub path: String,
+    /// Idle read-only connections kept open for the API.
+    pub read_connections: usize,
+    /// Chat messages that may wait for the writer before listeners are slowed down.
+    pub write_queue: usize,
+    /// Most chat messages inserted in one transaction.
+    pub max_batch: usize,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        Self {
+            backend: StoreBackend::Sqlite,
+            path: "chat_messages.db".to_string(),
+            read_connections: 4,
+            write_queue: 10_000,
+            max_batch: 500,
+        }
+    }
+}
+
+/// Where messages, channels, settings, the audit log and accounts are kept.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+    /// The database file at `database.path`.
+    Sqlite,
+    /// Nothing is written to disk and everything is lost on restart.
+    Memory,
+}
+
+impl std::str::FromStr for StoreBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sqlite" => Ok(StoreBackend::Sqlite),
+            "memory" => Ok(StoreBackend::Memory),
+            _ => Err(anyhow::anyhow!("Unknown database backend: {}", s)),
+        }
+    }
+}
+
+/// Which messages are deleted to keep the database from growing forever.
+/// Nothing is deleted unless one of the limits is set. Pending messages are
+/// always kept, since they are still waiting for a moderator.
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Messages older than this are deleted.
+    pub max_age_hours: Option<u64>,
+    /// Rejected messages older than this are deleted, usually much sooner than `max_age_hours`.
+    pub rejected_max_age_hours: Option<u64>,
+    /// Pending messages older than this expire, so the other limits can delete them.
+    pub pending_max_age_hours: Option<u64>,
+    /// Only this many of the newest messages of each channel are kept.
+    pub max_messages_per_channel: Option<usize>,
+    /// Never delete published messages, whatever the limits above say.
+    pub keep_published: bool,
+    /// How often the limits are enforced.
+    pub interval_minutes: u64,
+    /// How often the database file is compacted after messages were deleted; 0 never compacts.
+    pub vacuum_interval_hours: u64,
+}
+
+impl RetentionConfig {
+    /// Whether any limit is set.
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_hours.is_some()
+            || self.rejected_max_age_hours.is_some()
+            || self.pending_max_age_hours.is_some()
+            || self.max_messages_per_channel.is_some()
+    }
+}
+
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        Self {
+            max_age_hours: None,
+            rejected_max_age_hours: None,
+            pending_max_age_hours: None,
+            max_messages_per_channel: None,
+            keep_published: true,
+            interval_minutes: 60,
+            vacuum_interval_hours: 24,
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenerConfig {
+    /// Reconnect to the channels that were being listened to before the restart.
+    pub restore_on_start: bool,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl ListenerConfig {
+    pub fn initial_backoff(&self) -> Duration {
+        Duration::from_millis(self.initial_backoff_ms)
+    }
+
+    pub fn max_backoff(&self) -> Duration {
+        Duration::from_millis(self.max_backoff_ms)
+    }
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            restore_on_start: true,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 60_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FilterConfig {
+    /// Filters applied before the ones stored in the database. They have no id
+    /// and cannot be deleted through the API.
+    pub rules: Vec<MessageFilter>,
+}
+
+#[derive(Clone, Debug, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Password of the `admin` owner account when it is first created. An
+    /// existing account keeps its password unless started with `--reset-admin`.
+    pub admin_password: Option<String>,
+    /// File to read `admin_password` from instead, e.g. a container secret.
+    /// A trailing newline is ignored.
+    pub admin_password_file: Option<String>,
+    /// Set by `--reset-admin`: applies the admin password to an existing
+    /// `admin` account and makes it an owner again.
+    #[serde(skip)]
+    pub reset_admin: bool,
+    pub session_ttl_hours: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            admin_password: None,
+            admin_password_file: None,
+            reset_admin: false,
+            session_ttl_hours: 7 * 24,
+        }
+    }
+}
+
+impl Config {
+    /// Builds the configuration from the command line, the config file it
+    /// names and the environment, then validates it.
+    pub fn load() -> anyhow::Result<Config> {
+        let args = Args::parse();
+
+        let mut config = match &args.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Failed to read config file {}: {}", path, e))?;
+                toml::from_str(&text)
+                    .map_err(|e| anyhow::anyhow!("Invalid config file {}: {}", path, e))?
+            }
+            None => Config::default(),
+        };
+
+        config.apply_env()?;
+        config.apply_args(args);
+        config.validate()?;
+
+        info!("Starting server on http://{}:{}", config.server.host, config.server.port);
+        Ok(config)
+    }
+
+    fn apply_env(&mut self) -> anyhow::Result<()> {
+        env_override("CHAT_HOST", &mut self.server.host)?;
+        env_override("CHAT_PORT", &mut self.server.port)?;
+        env_override("CHAT_STATIC_DIR", &mut self.server.static_dir)?;
+        env_override("CHAT_BROADCAST_CAPACITY", &mut self.server.broadcast_capacity)?;
+        if let Ok(origins) = std::env::var("CHAT_ALLOWED_ORIGINS") {
+            self.server.allowed_origins = origins.split(',').map(|origin| origin.trim().to_string()).filter(|origin| !origin.is_empty()).collect();
+        }
+        env_override("CHAT_DATABASE_BACKEND", &mut self.database.backend)?;
+        env_override("CHAT_DATABASE_PATH", &mut self.database.path)?;
+        env_override("CHAT_RESTORE_LISTENERS", &mut self.listeners.restore_on_start)?;
+        env_override("CHAT_INITIAL_BACKOFF_MS", &mut self.listeners.initial_backoff_ms)?;
+        env_override("CHAT_MAX_BACKOFF_MS", &mut self.listeners.max_backoff_ms)?;
+        if let Ok(password) = std::env::var("CHAT_ADMIN_PASSWORD") {
+            self.auth.admin_password = Some(password);
+            self.auth.admin_password_file = None;
+        }
+        if let Ok(path) = std::env::var("CHAT_ADMIN_PASSWORD_FILE") {
+            self.auth.admin_password_file = Some(path);
+            self.auth.admin_password = None;
+        }
+        env_override("CHAT_SESSION_TTL_HOURS", &mut self.auth.session_ttl_hours)?;
+        Ok(())
+    }
+
+    fn apply_args(&mut self, args: Args) {
+        if let Some(host) = args.host {
+            self.server.host = host;
+        }
+        if let Some(port) = args.port {
+            self.server.port = port;
+        }
+        if let Some(broadcast_capacity) = args.broadcast_capacity {
+            self.server.broadcast_capacity = broadcast_capacity;
+        }
+        if !args.allowed_origins.is_empty() {
+            self.server.allowed_origins = args.allowed_origins;
+        }
+        self.auth.reset_admin = args.reset_admin;
+    }
+
+    fn validate(&mut self) -> anyhow::Result<()> {
+        if self.server.host.to_lowercase() == "localhost" {
+            warn!("host is localhost; using 127.0.0.1");
+            self.server.host = "127.0.0.1".to_string();
+        }
+        if self.server.broadcast_capacity == 0 {
+            return Err(anyhow::anyhow!("server.broadcast_capacity must be at least 1"));
+        }
+        if self.database.path.is_empty() {
+            return Err(anyhow::anyhow!("database.path must not be empty"));
+        }
+        if self.database.write_queue == 0 || self.database.max_batch == 0 {
+            return Err(anyhow::anyhow!("database.write_queue and database.max_batch must be at least 1"));
+        }
+        if self.retention.interval_minutes == 0 {
+            return Err(anyhow::anyhow!("retention.interval_minutes must be at least 1"));
+        }
+        if self.retention.max_messages_per_channel == Some(0) {
+            return Err(anyhow::anyhow!("retention.max_messages_per_channel must be at least 1"));
+        }
+        if !std::path::Path::new(&self.server.static_dir).is_dir() {
+            warn!("Static directory {} does not exist; the admin page will not be served", self.server.static_dir);
+        }
+        if self.listeners.initial_backoff_ms == 0 || self.listeners.max_backoff_ms < self.listeners.initial_backoff_ms {
+            return Err(anyhow::anyhow!("listeners.initial_backoff_ms must be at least 1 and at most listeners.max_backoff_ms"));
+        }
+        if self.auth.session_ttl_hours == 0 {
+            return Err(anyhow::anyhow!("auth.session_ttl_hours must be at least 1"));
+        }
+        if let Some(path) = &self.auth.admin_password_file {
+            if self.auth.admin_password.is_some() {
+                return Err(anyhow::anyhow!("Set auth.admin_password or auth.admin_password_file, not both"));
+            }
+            let password = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read admin password file {}: {}", path, e))?;
+            self.auth.admin_password = Some(password.trim_end_matches(['\r', '\n']).to_string());
+        }
+        if self.auth.admin_password.as_deref() == Some("") {
+            return Err(anyhow::anyhow!("auth.admin_password must not be empty"));
+        }
+        if self.auth.reset_admin && self.auth.admin_password.is_none() {
+            return Err(anyhow::anyhow!("--reset-admin needs an admin password from CHAT_ADMIN_PASSWORD, auth.admin_password or auth.admin_password_file"));
+        }
+        for origin in &self.server.allowed_origins {
+            origin.parse::<axum::http::HeaderValue>()
+                .map_err(|_| anyhow::anyhow!("Invalid allowed origin: {}", origin))?;
+        }
+        crate::filters::compile(self.filters.rules.clone())
+            .map_err(|e| anyhow::anyhow!("Invalid filter in filters.rules: {}", e))?;
+        Ok(())
+    }
+}
+
+/// Replaces `value` with the parsed environment variable `name`, if it is set.
+fn env_override<T: std::str::FromStr>(name: &str, value: &mut T) -> anyhow::Result<()>
+where
+    T::Err: std::fmt::Display,
+{
+    if let Ok(raw) = std::env::var(name) {
+        *value = raw.parse().map_err(|e| anyhow::anyhow!("Invalid {}={}: {}", name, raw, e))?;
+    }
+    Ok(())
+}
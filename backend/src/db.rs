@@ -0,0 +1,165 @@
+//! Connections to the SQLite database.
+//!
+//! The database runs in WAL mode so reads never wait for writes. Incoming chat
+//! messages, which can arrive by the hundred during a raid, are queued to the
+//! writer thread and stored in batches. Read-only queries borrow a connection
+//! from a `ReadPool` so they do not compete with writes for the lock.
+
+use std::{ops::Deref, sync::{Arc, Mutex}};
+
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::{listeners, models::{AppState, ChatMessage, UserList}, protocol::AdminEvent};
+
+/// How long a connection waits for a lock held by another connection before giving up.
+const BUSY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Opens a connection with the settings every connection shares.
+pub fn open(path: &str) -> rusqlite::Result<rusqlite::Connection> {
+    let conn = rusqlite::Connection::open(path)?;
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    let journal_mode: String = conn.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+    if !journal_mode.eq_ignore_ascii_case("wal") {
+        warn!("Database {} is in {} mode instead of WAL", path, journal_mode);
+    }
+    // Safe with WAL: a power loss can lose the last commits but not corrupt the file.
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    Ok(conn)
+}
+
+/// Read-only connections handed out to handlers that only query.
+pub struct ReadPool {
+    path: String,
+    idle: Mutex<Vec<rusqlite::Connection>>,
+    /// Most idle connections kept open; busier moments open extra ones that are closed after use.
+    max_idle: usize,
+}
+
+impl ReadPool {
+    pub fn new(path: &str, max_idle: usize) -> Self {
+        Self {
+            path: path.to_string(),
+            idle: Mutex::new(Vec::new()),
+            max_idle,
+        }
+    }
+
+    pub fn get(&self) -> rusqlite::Result<PooledConnection<'_>> {
+        let conn = match self.idle.lock().unwrap().pop() {
+            Some(conn) => conn,
+            None => {
+                let conn = open(&self.path)?;
+                conn.pragma_update(None, "query_only", true)?;
+                conn
+            }
+        };
+        Ok(PooledConnection { conn: Some(conn), pool: self })
+    }
+}
+
+/// A connection borrowed from a `ReadPool`, returned to it when dropped.
+pub struct PooledConnection<'a> {
+    conn: Option<rusqlite::Connection>,
+    pool: &'a ReadPool,
+}
+
+impl Deref for PooledConnection<'_> {
+    type Target = rusqlite::Connection;
+
+    fn deref(&self) -> &rusqlite::Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        let mut idle = self.pool.idle.lock().unwrap();
+        if idle.len() < self.pool.max_idle {
+            idle.extend(self.conn.take());
+        }
+    }
+}
+
+/// A chat message waiting to be stored by the writer thread.
+pub struct QueuedMessage {
+    pub message: ChatMessage,
+    /// The list the author is on, which decides whether moderators see the
+    /// message and how it is published.
+    pub user_list: Option<UserList>,
+}
+
+/// Queues chat messages for the writer thread.
+pub struct MessageWriter {
+    sender: mpsc::Sender<QueuedMessage>,
+}
+
+impl MessageWriter {
+    /// Creates the queue; the writer thread is started with `spawn_writer`
+    /// once the state holding the writer exists.
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<QueuedMessage>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (Self { sender }, receiver)
+    }
+
+    /// Queues a message, waiting while the queue is full.
+    pub async fn send(&self, queued: QueuedMessage) {
+        if let Err(e) = self.sender.send(queued).await {
+            warn!("Message writer stopped, dropping message {}", e.0.message.id);
+        }
+    }
+}
+
+/// Starts the thread that stores queued messages. Whatever is queued when it
+/// wakes up is inserted in one transaction, up to `max_batch` messages.
+pub fn spawn_writer(state: Arc<AppState>, mut receiver: mpsc::Receiver<QueuedMessage>, max_batch: usize) {
+    // Rules may schedule delayed publishes, which need the runtime.
+    let runtime = tokio::runtime::Handle::current();
+    std::thread::Builder::new()
+        .name("db-writer".to_string())
+        .spawn(move || {
+            let _runtime = runtime.enter();
+            while let Some(first) = receiver.blocking_recv() {
+                let mut batch = vec![first];
+                while batch.len() < max_batch {
+                    match receiver.try_recv() {
+                        Ok(queued) => batch.push(queued),
+                        Err(_) => break,
+                    }
+                }
+                write_batch(&state, batch);
+            }
+            info!("Message writer stopped");
+        })
+        .expect("Failed to start message writer");
+}
+
+fn write_batch(state: &Arc<AppState>, batch: Vec<QueuedMessage>) {
+    let messages: Vec<ChatMessage> = batch.iter().map(|queued| queued.message.clone()).collect();
+
+    let stored: Vec<QueuedMessage> = {
+        let _order = state.event_order.lock().unwrap();
+        let inserted = match state.store.insert_messages(&messages) {
+            Ok(inserted) => inserted,
+            Err(e) => {
+                warn!("Failed to store message batch: {:?}", e);
+                return;
+            }
+        };
+
+        let stored: Vec<QueuedMessage> = batch.into_iter()
+            .zip(inserted)
+            .filter_map(|(queued, inserted)| inserted.then_some(queued))
+            .collect();
+
+        // Blocked users are kept for the record but never reach the admin panel.
+        for queued in stored.iter().filter(|queued| queued.user_list != Some(UserList::Blocked)) {
+            state.admin_panel_sender.send(AdminEvent::Message(queued.message.clone()));
+        }
+        stored
+    };
+
+    for queued in &stored {
+        listeners::apply_rules(state, queued);
+    }
+}
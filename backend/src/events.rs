@@ -0,0 +1,96 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use tokio::sync::broadcast;
+
+use crate::protocol::AdminEvent;
+
+/// An admin event tagged with its position in the admin event stream.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SequencedEvent {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: AdminEvent,
+}
+
+/// Broadcasts admin events with increasing sequence numbers and keeps the most
+/// recent ones so a reconnecting admin can resume where it left off.
+///
+/// Sequence numbers start over with every process, so each broadcaster has its
+/// own stream id and a resume only counts within the same stream.
+pub struct AdminBroadcaster {
+    stream: String,
+    sender: broadcast::Sender<Arc<SequencedEvent>>,
+    history: Mutex<History>,
+}
+
+struct History {
+    last_seq: u64,
+    events: VecDeque<Arc<SequencedEvent>>,
+    capacity: usize,
+}
+
+/// Where a new subscriber starts reading the event stream.
+pub enum Resume {
+    /// Every event after the requested sequence number is still buffered.
+    Backlog(Vec<Arc<SequencedEvent>>),
+    /// The requested events are gone (or none were requested), so the client
+    /// needs a fresh snapshot taken at this sequence number.
+    Snapshot(u64),
+}
+
+impl AdminBroadcaster {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self {
+            stream: uuid::Uuid::now_v7().to_string(),
+            sender,
+            history: Mutex::new(History {
+                last_seq: 0,
+                events: VecDeque::with_capacity(capacity),
+                capacity,
+            }),
+        }
+    }
+
+    /// Identifies this run of the event stream; sent with every snapshot.
+    pub fn stream(&self) -> &str {
+        &self.stream
+    }
+
+    /// Sends an event to every admin socket, returning its sequence number.
+    pub fn send(&self, event: AdminEvent) -> u64 {
+        let mut history = self.history.lock().unwrap();
+        history.last_seq += 1;
+
+        let event = Arc::new(SequencedEvent { seq: history.last_seq, event });
+        if history.events.len() == history.capacity {
+            history.events.pop_front();
+        }
+        history.events.push_back(event.clone());
+
+        let _ = self.sender.send(event);
+        history.last_seq
+    }
+
+    /// Subscribes to future events and works out how to catch up from `since`
+    /// in `stream`. A sequence number from another stream gets a snapshot.
+    ///
+    /// Both happen under the history lock, so no event can fall between the
+    /// backlog or snapshot and the live stream.
+    pub fn subscribe(&self, stream: Option<&str>, since: Option<u64>) -> (broadcast::Receiver<Arc<SequencedEvent>>, Resume) {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let since = since.filter(|_| stream == Some(self.stream.as_str()));
+
+        let resume = match since {
+            Some(since) if since == history.last_seq => Resume::Backlog(Vec::new()),
+            Some(since) if since < history.last_seq
+                && history.events.front().is_some_and(|event| event.seq <= since + 1) => {
+                Resume::Backlog(history.events.iter().filter(|event| event.seq > since).cloned().collect())
+            }
+            _ => Resume::Snapshot(history.last_seq),
+        };
+
+        (receiver, resume)
+    }
+}
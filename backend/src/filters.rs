@@ -0,0 +1,155 @@
+use regex::Regex;
+use tracing::warn;
+
+use crate::models::{ChatMessage, FilterAction, FilterKind, MessageFilter, MessageStatus};
+
+const LINK_PATTERN: &str = r"(?i)\b(?:https?://|www\.)\S+|\b[a-z0-9-]+(?:\.[a-z0-9-]+)*\.(?:com|net|org|io|gg|tv|ly|me|co|xyz|ru|info|link|app|dev)\b\S*";
+
+/// A filter with its pattern compiled once, ready to run on every message.
+pub struct CompiledFilter {
+    pub filter: MessageFilter,
+    matcher: Matcher,
+}
+
+enum Matcher {
+    Pattern(Regex),
+    MaxLength(usize),
+}
+
+impl CompiledFilter {
+    pub fn new(filter: MessageFilter) -> anyhow::Result<Self> {
+        let matcher = match &filter.kind {
+            FilterKind::BannedWords { words } => {
+                if words.is_empty() {
+                    return Err(anyhow::anyhow!("A banned words filter needs at least one word"));
+                }
+                if words.iter().any(|word| word.trim().is_empty()) {
+                    return Err(anyhow::anyhow!("Banned words must not be empty"));
+                }
+                let words = words.iter().map(|word| word_pattern(word)).collect::<Vec<_>>().join("|");
+                Matcher::Pattern(Regex::new(&format!("(?i){}", words))?)
+            }
+            FilterKind::Regex { pattern } => {
+                let regex = Regex::new(pattern)?;
+                // Such a pattern, the empty one included, matches every message.
+                if regex.is_match("") {
+                    return Err(anyhow::anyhow!("Regex {} matches empty text", pattern));
+                }
+                Matcher::Pattern(regex)
+            }
+            FilterKind::Links => Matcher::Pattern(Regex::new(LINK_PATTERN)?),
+            FilterKind::MaxLength { max_length } => Matcher::MaxLength(*max_length),
+        };
+
+        Ok(Self { filter, matcher })
+    }
+
+    fn matches(&self, content: &str) -> bool {
+        match &self.matcher {
+            Matcher::Pattern(regex) => regex.is_match(content),
+            Matcher::MaxLength(max_length) => content.chars().count() > *max_length,
+        }
+    }
+
+    fn mask(&self, content: &str) -> String {
+        match &self.matcher {
+            Matcher::Pattern(regex) => regex
+                .replace_all(content, |caps: &regex::Captures| "*".repeat(caps[0].chars().count()))
+                .into_owned(),
+            Matcher::MaxLength(max_length) => content.chars().take(*max_length).collect(),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.filter.kind {
+            FilterKind::BannedWords { .. } => "banned_words",
+            FilterKind::Regex { .. } => "regex",
+            FilterKind::Links => "link",
+            FilterKind::MaxLength { .. } => "max_length",
+        }
+    }
+}
+
+/// Matches `word` as a whole word. A `\b` boundary only works next to a word
+/// character, so edges like the `+` of "c++" or the `@` of "@user" go without.
+fn word_pattern(word: &str) -> String {
+    let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+    format!(
+        "(?:{}{}{})",
+        if is_word_char(word.chars().next()) { r"\b" } else { "" },
+        regex::escape(word),
+        if is_word_char(word.chars().next_back()) { r"\b" } else { "" },
+    )
+}
+
+pub fn compile(filters: Vec<MessageFilter>) -> anyhow::Result<Vec<CompiledFilter>> {
+    filters.into_iter().map(CompiledFilter::new).collect()
+}
+
+/// Compiles the filters from the config, which must all be valid, followed by
+/// the stored ones. A stored filter that no longer compiles, e.g. one saved
+/// before empty word lists were refused, is skipped with a warning.
+pub fn load(configured: Vec<MessageFilter>, stored: Vec<MessageFilter>) -> anyhow::Result<Vec<CompiledFilter>> {
+    let mut filters = compile(configured)?;
+    for filter in stored {
+        let id = filter.id;
+        match CompiledFilter::new(filter) {
+            Ok(filter) => filters.push(filter),
+            Err(e) => warn!("Ignoring stored filter {:?}: {}", id, e),
+        }
+    }
+    Ok(filters)
+}
+
+/// Runs every filter on a message before it is stored.
+/// Returns `None` if the message should be dropped.
+pub fn apply(filters: &[CompiledFilter], mut chat_message: ChatMessage) -> Option<ChatMessage> {
+    for filter in filters {
+        if !filter.matches(&chat_message.content) {
+            continue;
+        }
+
+        match filter.filter.action {
+            FilterAction::Drop => return None,
+            FilterAction::Reject => chat_message.status = MessageStatus::Rejected,
+            FilterAction::Mask => chat_message.content = filter.mask(&chat_message.content),
+            FilterAction::Flag => chat_message.flags.push(filter.name().to_string()),
+        }
+    }
+
+    Some(chat_message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(kind: FilterKind) -> MessageFilter {
+        MessageFilter { id: None, kind, action: FilterAction::Drop }
+    }
+
+    fn banned_words(words: &[&str]) -> anyhow::Result<CompiledFilter> {
+        CompiledFilter::new(filter(FilterKind::BannedWords { words: words.iter().map(|word| word.to_string()).collect() }))
+    }
+
+    #[test]
+    fn rejects_filters_that_match_everything() {
+        assert!(banned_words(&[]).is_err());
+        assert!(banned_words(&["spam", ""]).is_err());
+        assert!(banned_words(&[" "]).is_err());
+        assert!(CompiledFilter::new(filter(FilterKind::Regex { pattern: String::new() })).is_err());
+        assert!(CompiledFilter::new(filter(FilterKind::Regex { pattern: "a*".to_string() })).is_err());
+    }
+
+    #[test]
+    fn banned_words_match_whole_words() {
+        let filter = banned_words(&["spam", "c++", "@user"]).unwrap();
+        assert!(filter.matches("no SPAM here"));
+        assert!(!filter.matches("spammer"));
+        assert!(filter.matches("I like c++ a lot"));
+        assert!(!filter.matches("abc++"));
+        assert!(filter.matches("hi @user!"));
+        assert!(!filter.matches("hi @username"));
+        assert_eq!(filter.mask("spam and c++"), "**** and ***");
+    }
+}
@@ -0,0 +1,236 @@
+use std::{sync::{Arc, Mutex}, time::Duration};
+
+use futures_util::StreamExt;
+use tracing::{info, warn};
+
+use crate::{
+    config::ListenerConfig,
+    db::QueuedMessage,
+    filters,
+    models::{AppState, ChatMessage, Listener, ListenerState, ListenerStatus, MessageStatus, UserList},
+    protocol::AdminEvent,
+    rules,
+    sources::{ChatSource, MessageStream, NoActiveStream},
+};
+
+pub fn listen_to_channel(
+    state: Arc<AppState>,
+    platform: &str,
+    name: &str,
+) -> anyhow::Result<()> {
+    match start_listener(&state, platform, name) {
+        Ok(()) => state.store.set_channel_listen(platform, name, true),
+        Err(e) => {
+            if let Err(store_error) = state.store.set_channel_error(platform, name, Some(&e.to_string())) {
+                warn!("Failed to record error for {} channel {}: {:?}", platform, name, store_error);
+            }
+            Err(e)
+        }
+    }
+}
+
+fn start_listener(state: &Arc<AppState>, platform: &str, name: &str) -> anyhow::Result<()> {
+    let source = state.sources.get(platform)
+        .ok_or_else(|| anyhow::anyhow!("Unknown platform: {}", platform))?;
+
+    let mut listened_channels = state.listened_channels.lock().unwrap();
+    let platform_map = listened_channels.entry(platform.to_string()).or_default();
+
+    if platform_map.contains_key(name) {
+        info!("Already listening to {} channel: {}", platform, name);
+        return Ok(());
+    }
+
+    let status = Arc::new(Mutex::new(ListenerStatus {
+        platform: platform.to_string(),
+        channel: name.to_string(),
+        state: ListenerState::Connecting,
+        message_count: 0,
+        last_message_at: None,
+        last_error: None,
+        updated_at: chrono::Utc::now().timestamp_millis() as u64,
+    }));
+    state.admin_panel_sender.send(AdminEvent::ListenerStatus(status.lock().unwrap().clone()));
+
+    let handle = tokio::spawn(supervise(state.clone(), source, name.to_string(), status.clone()));
+    platform_map.insert(name.to_string(), Listener { handle, status });
+
+    Ok(())
+}
+
+pub fn get_listener_statuses(state: &AppState) -> Vec<ListenerStatus> {
+    state.listened_channels.lock().unwrap()
+        .values()
+        .flat_map(|channels| channels.values())
+        .map(|listener| listener.status.lock().unwrap().clone())
+        .collect()
+}
+
+/// Updates the listener state and notifies the admin panel if anything changed.
+fn set_listener_state(
+    state: &AppState,
+    status: &Mutex<ListenerStatus>,
+    listener_state: ListenerState,
+    error: Option<String>,
+) {
+    let snapshot = {
+        let mut status = status.lock().unwrap();
+        if status.state == listener_state && (error.is_none() || status.last_error == error) {
+            return;
+        }
+
+        status.state = listener_state;
+        if error.is_some() {
+            status.last_error = error;
+        }
+        status.updated_at = chrono::Utc::now().timestamp_millis() as u64;
+        status.clone()
+    };
+
+    state.admin_panel_sender.send(AdminEvent::ListenerStatus(snapshot));
+}
+
+/// Keeps a channel connected until the task is aborted by `stop_listening_to_channel`.
+///
+/// Whenever the connection fails or the message stream ends, the listener waits
+/// with exponential backoff and jitter before reconnecting.
+async fn supervise(
+    state: Arc<AppState>,
+    source: Arc<dyn ChatSource>,
+    name: String,
+    status: Arc<Mutex<ListenerStatus>>,
+) {
+    let platform = source.platform();
+    let mut attempt = 0;
+
+    loop {
+        match source.connect(name.clone()).await {
+            Ok(stream) => {
+                info!("Connected to {} channel: {}", platform, name);
+                set_listener_state(&state, &status, ListenerState::Live, None);
+                if let Err(e) = state.store.set_channel_error(platform, &name, None) {
+                    warn!("Failed to clear error for {} channel {}: {:?}", platform, name, e);
+                }
+                let started = tokio::time::Instant::now();
+
+                run_stream(&state, &status, stream).await;
+
+                // Only a connection that stayed up for a while resets the backoff,
+                // otherwise a stream that closes right away would reconnect in a tight loop.
+                if started.elapsed() >= state.config.listeners.max_backoff() {
+                    attempt = 0;
+                }
+                warn!("{} listener for {} ended", platform, name);
+                set_listener_state(&state, &status, ListenerState::Reconnecting, None);
+            }
+            Err(e) if e.is::<NoActiveStream>() => {
+                info!("{}", e);
+                set_listener_state(&state, &status, ListenerState::WaitingForStream, None);
+            }
+            Err(e) => {
+                warn!("Failed to connect to {} channel {}: {:?}", platform, name, e);
+                set_listener_state(&state, &status, ListenerState::Failed, Some(e.to_string()));
+                if let Err(e) = state.store.set_channel_error(platform, &name, Some(&e.to_string())) {
+                    warn!("Failed to record error for {} channel {}: {:?}", platform, name, e);
+                }
+            }
+        }
+
+        let delay = backoff_delay(&state.config.listeners, attempt);
+        attempt += 1;
+        info!("Reconnecting to {} channel {} in {:?}", platform, name, delay);
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn run_stream(state: &Arc<AppState>, status: &Mutex<ListenerStatus>, mut stream: MessageStream) {
+    while let Some(message) = stream.next().await {
+        match message {
+            Ok(chat_message) => {
+                info!("{} message from {}: {}", chat_message.platform, chat_message.username, chat_message.content);
+
+                {
+                    let mut status = status.lock().unwrap();
+                    status.message_count += 1;
+                    status.last_message_at = Some(chat_message.timestamp);
+                }
+
+                if let Some(queued) = screen_message(state, chat_message) {
+                    state.message_writer.send(queued).await;
+                }
+            }
+            Err(e) => {
+                warn!("{:?}", e);
+                status.lock().unwrap().last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+/// Checks the author against the user lists and runs the filters, returning
+/// the message to store or `None` if a filter dropped it.
+fn screen_message(state: &AppState, mut chat_message: ChatMessage) -> Option<QueuedMessage> {
+    let user_list = state.user_lists.read().unwrap().lookup(&chat_message);
+
+    if user_list == Some(UserList::Blocked) {
+        chat_message.status = MessageStatus::Rejected;
+        return Some(QueuedMessage { message: chat_message, user_list });
+    }
+
+    let Some(chat_message) = filters::apply(&state.filters.read().unwrap(), chat_message) else {
+        info!("Message dropped by filter");
+        return None;
+    };
+    Some(QueuedMessage { message: chat_message, user_list })
+}
+
+/// Runs the auto-publish rules for a message the writer has stored. Flagged
+/// messages are left for a moderator, even from trusted users.
+pub fn apply_rules(state: &Arc<AppState>, queued: &QueuedMessage) {
+    if queued.message.status != MessageStatus::Pending || !queued.message.flags.is_empty() {
+        return;
+    }
+
+    if queued.user_list == Some(UserList::Trusted) {
+        rules::publish(state, &queued.message);
+    } else {
+        rules::apply(state, &queued.message);
+    }
+}
+
+/// Exponential backoff capped at the configured maximum, with up to 50% random jitter added.
+fn backoff_delay(config: &ListenerConfig, attempt: u32) -> Duration {
+    let base = config.initial_backoff().saturating_mul(2u32.saturating_pow(attempt)).min(config.max_backoff());
+    let jitter_range = base.as_millis() as u64 / 2;
+    let jitter = if jitter_range > 0 {
+        chrono::Utc::now().timestamp_subsec_nanos() as u64 % jitter_range
+    } else {
+        0
+    };
+    base + Duration::from_millis(jitter)
+}
+
+pub fn stop_listening_to_channel(
+    platform: &str,
+    name: &str,
+    state: &AppState,
+) -> anyhow::Result<()> {
+    state.store.set_channel_listen(platform, name, false)?;
+
+    let mut channels = state.listened_channels.lock().unwrap();
+    if let Some(platform_map) = channels.get_mut(platform) {
+        if let Some(listener) = platform_map.remove(name) {
+            listener.handle.abort();
+            info!("Stopped listening to {} channel: {}", platform, name);
+            state.admin_panel_sender.send(AdminEvent::ListenerStopped {
+                platform: platform.to_string(),
+                channel: name.to_string(),
+            });
+        } else {
+            info!("No active listener found for {} channel: {}", platform, name);
+        }
+    } else {
+        info!("No active listeners for platform: {}", platform);
+    }
+    Ok(())
+}
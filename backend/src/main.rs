@@ -1,34 +1,63 @@
-use std::{collections::HashMap, sync::{atomic::{AtomicUsize, Ordering}, Arc, Mutex}};
+use std::{collections::HashMap, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc, Mutex, RwLock}};
 use tracing::{info, warn};
-use axum::{extract::{ws::WebSocket, Path, Query, State, WebSocketUpgrade}, http::StatusCode, response::Response, routing::{any, delete, get, post}, Json, Router};
-use tokio::sync::broadcast;
-use tower_http::cors::{Any, CorsLayer};
+use axum::{extract::{ws::WebSocket, Path, Query, State, WebSocketUpgrade}, http::{header, HeaderMap, Method, StatusCode}, middleware, response::{IntoResponse, Response}, routing::{any, delete, get, post}, Json, Router};
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
+use tower_http::cors::{AllowOrigin, CorsLayer};
 use tower_http::services::ServeDir;
 use futures_util::{sink::SinkExt, stream::{StreamExt, SplitSink, SplitStream}};
 
-use crate::{models::AppState, utils::parse_args};
+use crate::models::AppState;
 
 mod utils;
 mod models;
+mod sources;
+mod listeners;
+mod rules;
+mod filters;
+mod commands;
+mod events;
+mod protocol;
+mod auth;
+mod config;
+mod migrations;
+mod db;
+mod store;
+mod retention;
 
-async fn client_ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Response {
-    ws.on_upgrade(|socket| client_socket_handler(socket, state))
+/// Most messages an overlay can ask to have replayed on connect.
+const MAX_CLIENT_BACKLOG: usize = 500;
+
+async fn client_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> Response {
+    if !auth::is_overlay_allowed(&state, &headers, params.get("token").map(String::as_str)) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let backlog = params.get("backlog").and_then(|v| v.parse::<usize>().ok()).unwrap_or(0).min(MAX_CLIENT_BACKLOG);
+    let since = params.get("since").and_then(|v| v.parse::<u64>().ok());
+    ws.on_upgrade(move |socket| client_socket_handler(socket, state, backlog, since))
 }
 
-async fn client_socket_handler(mut socket: WebSocket, state: Arc<AppState>) {
+async fn client_socket_handler(socket: WebSocket, state: Arc<AppState>, backlog: usize, since: Option<u64>) {
     let connection_count = state.active_connections.fetch_add(1, Ordering::Relaxed);
     info!("New client connection. Total: {}", connection_count + 1);
 
     let (sender, receiver) = socket.split();
-    let mut message_receiver = state.client_sender.subscribe();
+
+    let (message_receiver, replay) = client_catch_up(&state, backlog, since);
 
     let state_clone = state.clone();
     let reader_handle = tokio::spawn(async move {
         reader_client_task(receiver, state_clone).await;
     });
 
+    let state_clone = state.clone();
     let writer_handle = tokio::spawn(async move {
-        writer_client_task(sender, message_receiver).await;
+        writer_client_task(sender, state_clone, replay, message_receiver).await;
     });
 
     tokio::select! {
@@ -44,7 +73,7 @@ async fn client_socket_handler(mut socket: WebSocket, state: Arc<AppState>) {
     info!("Client connection closed. Total: {}", final_count - 1);
 }
 
-async fn reader_client_task(mut receiver: SplitStream<WebSocket>, state: Arc<AppState>) {
+async fn reader_client_task(mut receiver: SplitStream<WebSocket>, _state: Arc<AppState>) {
     while let Some(msg) = receiver.next().await {
         match msg {
             Ok(msg) => {
@@ -58,34 +87,114 @@ async fn reader_client_task(mut receiver: SplitStream<WebSocket>, state: Arc<App
     }
 }
 
-async fn writer_client_task(mut sender: SplitSink<WebSocket, axum::extract::ws::Message>, mut message_receiver: broadcast::Receiver<models::ChatMessage>) {
-    while let Ok(chat_message) = message_receiver.recv().await {
-        let msg_text = serde_json::to_string(&chat_message).unwrap_or_else(|_| "{}".to_string());
-        if sender.send(axum::extract::ws::Message::Text(msg_text.into())).await.is_err() {
+/// Subscribes an overlay to live events and loads the events it should get
+/// first: up to `backlog` messages published after `since` and the last clear.
+///
+/// Publishing happens under `event_order`, so subscribing and reading the
+/// backlog under it too means no message is missed or sent twice.
+fn client_catch_up(
+    state: &AppState,
+    backlog: usize,
+    since: Option<u64>,
+) -> (broadcast::Receiver<protocol::ClientEvent>, Vec<protocol::ClientEvent>) {
+    let _order = state.event_order.lock().unwrap();
+    let message_receiver = state.client_sender.subscribe();
+
+    let mut replay = Vec::new();
+    if backlog > 0 {
+        // `since` and the clear are both publish times, so a message received
+        // before the clear but published after it is still replayed.
+        let since = since.unwrap_or(0).max(state.screen_cleared_at.load(Ordering::Relaxed));
+        match state.store.get_published_messages(backlog, Some(since)) {
+            Ok(messages) => replay.extend(messages.iter().map(|message| protocol::ClientEvent::MessagePublished(message.for_overlay()))),
+            Err(e) => warn!("Failed to load client backlog: {:?}", e),
+        }
+    }
+    if let Some(pinned) = state.pinned_message.lock().unwrap().clone() {
+        replay.push(protocol::ClientEvent::MessagePinned(pinned));
+    }
+    (message_receiver, replay)
+}
+
+async fn send_text(sender: &mut SplitSink<WebSocket, axum::extract::ws::Message>, msg_text: String) -> bool {
+    sender.send(axum::extract::ws::Message::Text(msg_text.into())).await.is_ok()
+}
+
+async fn writer_client_task(
+    mut sender: SplitSink<WebSocket, axum::extract::ws::Message>,
+    state: Arc<AppState>,
+    mut replay: Vec<protocol::ClientEvent>,
+    mut message_receiver: broadcast::Receiver<protocol::ClientEvent>,
+) {
+    loop {
+        for event in replay.drain(..) {
+            let msg_text = protocol::frame(&event).unwrap_or_else(|_| "{}".to_string());
+            if !send_text(&mut sender, msg_text).await {
+                warn!("Error sending client message");
+                return;
+            }
+        }
+
+        let event = match message_receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(missed)) => {
+                // Tell the overlay what happened, then resend the latest published
+                // messages; overlays are expected to ignore ids they already show.
+                warn!("Client lagged behind by {} messages, resyncing", missed);
+                let backlog = (missed as usize).min(MAX_CLIENT_BACKLOG);
+                let (receiver, resync) = client_catch_up(&state, backlog, None);
+                message_receiver = receiver;
+                replay.push(protocol::ClientEvent::Lagged { missed });
+                replay.extend(resync);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        let msg_text = protocol::frame(&event).unwrap_or_else(|_| "{}".to_string());
+        if !send_text(&mut sender, msg_text).await {
             warn!("Error sending client message");
             break;
         }
     }
 }
 
-async fn admin_ws_handler(ws: WebSocketUpgrade, State(state): State<Arc<AppState>>) -> Response {
-    ws.on_upgrade(|socket| admin_socket_handler(socket, state))
+async fn admin_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::ViewerRole>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> Response {
+    let stream = params.get("stream").cloned();
+    let since = params.get("since").and_then(|v| v.parse::<u64>().ok());
+    ws.on_upgrade(move |socket| admin_socket_handler(socket, state, session, stream, since))
 }
 
-async fn admin_socket_handler(mut socket: WebSocket, state: Arc<AppState>) {
+async fn admin_socket_handler(
+    socket: WebSocket,
+    state: Arc<AppState>,
+    session: auth::Session,
+    stream: Option<String>,
+    since: Option<u64>,
+) {
     let connection_count = state.active_connections.fetch_add(1, Ordering::Relaxed);
     info!("New admin connection. Total: {}", connection_count + 1);
 
     let (sender, receiver) = socket.split();
-    let mut message_receiver = state.admin_panel_sender.subscribe();
+    let (reply_sender, reply_receiver) = mpsc::channel(100);
+
+    let subscription: commands::Subscription = Arc::new(Mutex::new(None));
+    let (message_receiver, catch_up) = admin_catch_up(&state, stream.as_deref(), since, &subscription);
 
     let state_clone = state.clone();
+    let reader_subscription = subscription.clone();
     let reader_handle = tokio::spawn(async move {
-        reader_admin_task(receiver, state_clone).await;
+        reader_admin_task(receiver, state_clone, session, reply_sender, reader_subscription).await;
     });
 
+    let state_clone = state.clone();
     let writer_handle = tokio::spawn(async move {
-        writer_admin_task(sender, message_receiver).await;
+        writer_admin_task(sender, state_clone, catch_up, message_receiver, reply_receiver, subscription).await;
     });
 
     tokio::select! {
@@ -103,10 +212,28 @@ async fn admin_socket_handler(mut socket: WebSocket, state: Arc<AppState>) {
 
 async fn reader_admin_task(
     mut receiver: SplitStream<WebSocket>,
-    state: Arc<AppState>
+    state: Arc<AppState>,
+    session: auth::Session,
+    reply_sender: mpsc::Sender<protocol::AdminEvent>,
+    subscription: commands::Subscription,
 ) {
     while let Some(msg) = receiver.next().await {
         match msg {
+            Ok(axum::extract::ws::Message::Text(text)) => {
+                info!("Received admin message: {}", text);
+
+                let reply = match serde_json::from_str::<protocol::AdminRequest>(&text) {
+                    Ok(request) => commands::handle_command(&state, &session, request, &subscription),
+                    Err(e) => protocol::AdminEvent::Error {
+                        request_id: None,
+                        message: format!("Invalid command: {}", e),
+                    },
+                };
+
+                if reply_sender.send(reply).await.is_err() {
+                    break;
+                }
+            }
             Ok(msg) => {
                 info!("Received admin message: {:?}", msg);
             }
@@ -118,83 +245,271 @@ async fn reader_admin_task(
     }
 }
 
-async fn writer_admin_task(mut sender: SplitSink<WebSocket, axum::extract::ws::Message>, mut message_receiver: broadcast::Receiver<models::ChatMessage>) {
-    while let Ok(chat_message) = message_receiver.recv().await {
-        let msg_text = serde_json::to_string(&chat_message).unwrap_or_else(|_| "{}".to_string());
-        if sender.send(axum::extract::ws::Message::Text(msg_text.into())).await.is_err() {
+/// Subscribes an admin socket to live events and builds the frames that bring
+/// it up to date: the missed events if they are still buffered in the same
+/// `stream`, otherwise a snapshot of the pending queue. Both are limited to
+/// the channels in `subscription`, like live events.
+///
+/// The snapshot is taken under `event_order` so nothing changes between
+/// reading the pending queue and subscribing to further events.
+fn admin_catch_up(
+    state: &AppState,
+    stream: Option<&str>,
+    since: Option<u64>,
+    subscription: &commands::Subscription,
+) -> (broadcast::Receiver<Arc<events::SequencedEvent>>, Vec<String>) {
+    let _order = state.event_order.lock().unwrap();
+    let (message_receiver, resume) = state.admin_panel_sender.subscribe(stream, since);
+
+    let catch_up: Vec<serde_json::Result<String>> = match resume {
+        events::Resume::Backlog(events) => events
+            .iter()
+            .filter(|event| commands::is_subscribed(subscription, &event.event))
+            .map(|event| protocol::frame(&**event))
+            .collect(),
+        events::Resume::Snapshot(seq) => {
+            let event = match state.store.get_pending_messages() {
+                Ok(messages) => protocol::AdminEvent::Snapshot {
+                    stream: state.admin_panel_sender.stream().to_string(),
+                    seq,
+                    messages: messages
+                        .into_iter()
+                        .filter(|message| commands::is_subscribed_to(subscription, message))
+                        .collect(),
+                },
+                Err(e) => protocol::AdminEvent::Error {
+                    request_id: None,
+                    message: format!("Failed to load pending messages: {:?}", e),
+                },
+            };
+            vec![protocol::frame(&event)]
+        }
+    };
+
+    (message_receiver, catch_up.into_iter().filter_map(Result::ok).collect())
+}
+
+async fn writer_admin_task(
+    mut sender: SplitSink<WebSocket, axum::extract::ws::Message>,
+    state: Arc<AppState>,
+    mut catch_up: Vec<String>,
+    mut message_receiver: broadcast::Receiver<Arc<events::SequencedEvent>>,
+    mut reply_receiver: mpsc::Receiver<protocol::AdminEvent>,
+    subscription: commands::Subscription,
+) {
+    let mut last_seq = None;
+
+    loop {
+        for msg_text in catch_up.drain(..) {
+            if !send_text(&mut sender, msg_text).await {
+                warn!("Error sending admin message");
+                return;
+            }
+        }
+
+        let msg_text = tokio::select! {
+            event = message_receiver.recv() => match event {
+                Ok(event) => {
+                    last_seq = Some(event.seq);
+                    if !commands::is_subscribed(&subscription, &event.event) {
+                        continue;
+                    }
+                    protocol::frame(&*event)
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Admin lagged behind by {} events, resyncing", missed);
+                    let (receiver, resync) = admin_catch_up(
+                        &state,
+                        Some(state.admin_panel_sender.stream()),
+                        last_seq,
+                        &subscription,
+                    );
+                    message_receiver = receiver;
+                    catch_up.extend(protocol::frame(protocol::AdminEvent::Lagged { missed }));
+                    catch_up.extend(resync);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            Some(reply) = reply_receiver.recv() => protocol::frame(&reply),
+        }.unwrap_or_else(|_| "{}".to_string());
+
+        if !send_text(&mut sender, msg_text).await {
             warn!("Error sending admin message");
             break;
         }
     }
 }
 
+fn message_status_response(
+    id: models::MessageId,
+    action: &str,
+    result: anyhow::Result<store::StatusChange>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match result {
+        Ok(store::StatusChange::Changed { .. }) => (StatusCode::OK,
+            Json(serde_json::json!({
+                "status": "success",
+                "message": format!("Message {} {}", id, action)
+            }))
+        ),
+        Ok(store::StatusChange::NotFound) => (StatusCode::NOT_FOUND,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Message {} not found", id)
+            }))
+        ),
+        Ok(store::StatusChange::NotAllowed(current)) => (StatusCode::CONFLICT,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Message {} is {} and cannot be {}", id, current.as_str(), action)
+            }))
+        ),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to update message {}: {:?}", id, e)
+            }))
+        ),
+    }
+}
+
 async fn publish_message(
     State(state): State<Arc<AppState>>,
-    Path(params): Path<std::collections::HashMap<String, String>>,
-    ) -> (StatusCode, Json<serde_json::Value>) {
-    if let Some(id) = params.get("id") {
-        let id_num = id.parse::<u128>().unwrap_or(0);
+    auth::Authorized(session, _): auth::Authorized<auth::ModeratorRole>,
+    Path(id): Path<models::MessageId>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    info!("Publishing message with id: {}", id);
 
-        info!("Publishing message with id: {}", id_num);
-        
-        utils::publish_message(
-            &state.db_conn.lock().unwrap(),
-            id_num,
-            &state.client_sender
-        ).expect("Failed to publish message");
+    let _order = state.event_order.lock().unwrap();
+    let result = utils::publish_message(&state, id, &session.username);
+
+    message_status_response(id, "published", result)
+}
+
+async fn reject_message(
+    State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::ModeratorRole>,
+    Path(id): Path<models::MessageId>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    info!("Rejecting message with id: {}", id);
+
+    let _order = state.event_order.lock().unwrap();
+    let result = utils::reject_message(&state, id, &session.username);
+
+    message_status_response(id, "rejected", result)
+}
+
+async fn unpublish_message(
+    State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::ModeratorRole>,
+    Path(id): Path<models::MessageId>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let moderator = session.username.as_str();
+
+    info!("Unpublishing message with id: {} by {}", id, moderator);
+
+    let _order = state.event_order.lock().unwrap();
+    let result = utils::unpublish_message(&state, id, moderator);
+
+    message_status_response(id, "unpublished", result)
+}
+
+#[derive(serde::Deserialize)]
+struct EditRequest {
+    /// Text to show instead of the original; null restores the original.
+    content: Option<String>,
+}
+
+async fn edit_message(
+    State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::ModeratorRole>,
+    Path(id): Path<models::MessageId>,
+    Json(request): Json<EditRequest>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    info!("Editing message with id: {}", id);
+
+    if let Err(e) = commands::validate_edit(request.content.as_deref()) {
+        return (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to edit message {}: {}", id, e)
+            }))
+        );
+    }
 
-        (StatusCode::OK, 
+    match commands::edit(&state, id, request.content.as_deref(), &session.username) {
+        Ok(Some(_)) => (StatusCode::OK,
             Json(serde_json::json!({
                 "status": "success",
-                "message": format!("Message {} published", id)
+                "message": format!("Message {} edited", id)
             }))
-        )
-    } else {
-        (StatusCode::BAD_REQUEST, 
+        ),
+
+        Ok(None) => (StatusCode::NOT_FOUND,
             Json(serde_json::json!({
                 "status": "error",
-                "message": "Missing id parameter"
+                "message": format!("Message {} not found", id)
             }))
-        )
+        ),
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to edit message {}: {:?}", id, e)
+            }))
+        ),
+    }
+}
+
+async fn confirm_message(
+    State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::ModeratorRole>,
+    Json(confirmation): Json<models::MessageConfirmation>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let id = confirmation.id;
+    let _order = state.event_order.lock().unwrap();
+
+    if confirmation.allowed {
+        info!("Publishing message with id: {}", id);
+        let result = utils::publish_message(&state, id, &session.username);
+        message_status_response(id, "published", result)
+    } else {
+        info!("Rejecting message with id: {}", id);
+        let result = utils::reject_message(&state, id, &session.username);
+        message_status_response(id, "rejected", result)
+    }
+}
+
+/// Records a channel action in the audit log. A failure is only logged, since
+/// the action itself already happened.
+fn audit_channel(state: &AppState, session: &auth::Session, action: models::AuditAction, platform: &str, channel: &str) {
+    let entry = models::AuditEntry::new(&session.username, action, platform, channel);
+    if let Err(e) = state.store.add_audit_entry(&entry) {
+        warn!("Failed to record {} of {} on {}: {:?}", action.as_str(), channel, platform, e);
     }
 }
 
 async fn listen_channel(
     State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::OwnerRole>,
     Path(params): Path<std::collections::HashMap<String, String>>,
 ) -> (StatusCode, Json<serde_json::Value>) {
     if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
         info!("Listening to channel {} on platform {}", id, platform);
 
-        let result = match platform.as_str() {
-            "twitch" => {
-                utils::listen_to_twitch(
-                    id.clone(),
-                    state.admin_panel_sender.clone(),
-                    state.db_conn.clone(),
-                    state.listened_channels.clone(),
-                ).await
-            },
-            "youtube" => {
-                utils::listen_to_youtube(
-                    id.clone(),
-                    state.admin_panel_sender.clone(),
-                    state.db_conn.clone(),
-                    state.listened_channels.clone(),
-                ).await
-            },
-            _ => {
-                Err(anyhow::anyhow!("Unknown platform: {}", platform))
-            }
-        };
+        let result = listeners::listen_to_channel(state.clone(), platform, id);
 
         match result {
-            Ok(_) => (StatusCode::OK, 
-                Json(serde_json::json!({
-                    "status": "success",
-                    "message": format!("Started listening to {} on {}", id, platform)
-                }))
-            ),
+            Ok(_) => {
+                audit_channel(&state, &session, models::AuditAction::Listen, platform, id);
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "message": format!("Started listening to {} on {}", id, platform)
+                    }))
+                )
+            }
             Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, 
                 Json(serde_json::json!({
                     "status": "error",
@@ -214,22 +529,26 @@ async fn listen_channel(
 
 async fn unlisten_channel(
     State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::OwnerRole>,
     Path(params): Path<std::collections::HashMap<String, String>>,
 ) -> (StatusCode, Json<serde_json::Value>) {
     if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
         info!("Unlistening from channel {} on platform {}", id, platform);
 
-        match utils::stop_listening_to_channel(
-            &platform.clone(),
-            &id.clone(),
-            state.listened_channels.clone()
+        match listeners::stop_listening_to_channel(
+            platform,
+            id,
+            &state,
         ) {
-            Ok(_) => (StatusCode::OK, 
-                Json(serde_json::json!({
-                    "status": "success",
-                    "message": format!("Stopped listening to {} on {}", id, platform)
-                }))
-            ),
+            Ok(_) => {
+                audit_channel(&state, &session, models::AuditAction::Unlisten, platform, id);
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "message": format!("Stopped listening to {} on {}", id, platform)
+                    }))
+                )
+            }
             Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, 
                 Json(serde_json::json!({
                     "status": "error",
@@ -251,19 +570,29 @@ async fn unlisten_channel(
 
 async fn add_channel(
     State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::OwnerRole>,
     Path(params): Path<std::collections::HashMap<String, String>>,
 ) -> (StatusCode, Json<serde_json::Value>) {
     if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
         info!("Adding channel {} on platform {}", id, platform);
 
-        match utils::add_channel(&state.db_conn.lock().unwrap(), &id, &platform) {
-            Ok(_) => (StatusCode::OK, 
+        match state.store.add_channel(id, platform) {
+            Ok(Some(_)) => {
+                audit_channel(&state, &session, models::AuditAction::AddChannel, platform, id);
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "message": format!("Channel {} on {} added", id, platform)
+                    }))
+                )
+            }
+            Ok(None) => (StatusCode::CONFLICT,
                 Json(serde_json::json!({
-                    "status": "success",
-                    "message": format!("Channel {} on {} added", id, platform)
+                    "status": "error",
+                    "message": format!("Channel {} on {} already exists", id, platform)
                 }))
             ),
-            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, 
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
                 Json(serde_json::json!({
                     "status": "error",
                     "message": format!("Failed to add channel {} on {}: {:?}", id, platform, e)
@@ -282,8 +611,9 @@ async fn add_channel(
 
 async fn get_channels(
     State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ViewerRole>,
 ) -> (StatusCode, Json<serde_json::Value>) {
-    match utils::get_channels(&state.db_conn.lock().unwrap()) {
+    match state.store.get_channels() {
         Ok(channels) => (StatusCode::OK, 
             Json(serde_json::json!({
                 "status": "success",
@@ -301,17 +631,26 @@ async fn get_channels(
 
 async fn delete_channel(
     State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::OwnerRole>,
     Path(params): Path<std::collections::HashMap<String, String>>,
 ) -> (StatusCode, Json<serde_json::Value>) {
     if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
-        info!("Deleting channel {} on platform {}", id, platform);        
-        match utils::delete_channel(&state.db_conn.lock().unwrap(), &platform, &id) {
-            Ok(_) => (StatusCode::OK, 
-                Json(serde_json::json!({
-                    "status": "success",
-                    "message": format!("Channel {} on {} deleted", id, platform)
-                }))
-            ),
+        info!("Deleting channel {} on platform {}", id, platform);
+
+        if let Err(e) = listeners::stop_listening_to_channel(platform, id, &state) {
+            warn!("Failed to stop listening to {} on {}: {:?}", id, platform, e);
+        }
+
+        match state.store.delete_channel(platform, id) {
+            Ok(_) => {
+                audit_channel(&state, &session, models::AuditAction::DeleteChannel, platform, id);
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "message": format!("Channel {} on {} deleted", id, platform)
+                    }))
+                )
+            }
             Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, 
                 Json(serde_json::json!({
                     "status": "error",
@@ -329,117 +668,899 @@ async fn delete_channel(
     }
 } 
 
-async fn get_messages(
+async fn get_rules(
     State(state): State<Arc<AppState>>,
-    Query(params): Query<std::collections::HashMap<String, String>>,
+    _: auth::Authorized<auth::ViewerRole>,
 ) -> (StatusCode, Json<serde_json::Value>) {
-    let limit = params.get("limit").and_then(|v| v.parse::<usize>().ok()).unwrap_or(50);
-    let before = params.get("before").and_then(|v| v.parse::<u64>().ok());
+    (StatusCode::OK,
+        Json(serde_json::json!({
+            "status": "success",
+            "rules": *state.publish_rules.read().unwrap()
+        }))
+    )
+}
 
-    match utils::get_messages(limit, before, &state.db_conn.lock().unwrap()) {
-        Ok(messages) => {
-            let json_messages: Vec<serde_json::Value> = messages.iter().map(|msg| {
-                serde_json::json!({
-                    "id": msg.id.to_string(),
-                    "platform": msg.platform,
-                    "channel": msg.channel,
-                    "username": msg.username,
-                    "content": msg.content,
-                    "additional_info": msg.additional_info,
-                    "timestamp": msg.timestamp,
-                    "published": msg.published
-                })
-            }).collect();
+/// Creates or replaces the rule for a channel. Use `*` as platform and id for
+/// the global rule.
+async fn save_rule(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Path(params): Path<std::collections::HashMap<String, String>>,
+    Json(mode): Json<models::PublishMode>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
+        info!("Saving publish rule for {} on {}: {:?}", id, platform, mode);
 
-            info!("Retrieved {} messages", json_messages.len());
+        let rule = models::PublishRule {
+            platform: platform.clone(),
+            channel: id.clone(),
+            mode,
+        };
 
-            (StatusCode::OK, 
+        // Locked first so concurrent changes reach the store and the cache in the same order.
+        let mut rules = state.publish_rules.write().unwrap();
+        match state.store.save_publish_rule(&rule).and_then(|_| state.store.get_publish_rules()) {
+            Ok(saved) => {
+                *rules = saved;
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "rule": rule
+                    }))
+                )
+            },
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
                 Json(serde_json::json!({
-                    "status": "success",
-                    "messages": json_messages
+                    "status": "error",
+                    "message": format!("Failed to save rule for {} on {}: {:?}", id, platform, e)
                 }))
-            )
-        },
+            ),
+        }
+    } else {
+        (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Missing platform or id parameter"
+            }))
+        )
+    }
+}
 
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, 
+async fn delete_rule(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Path(params): Path<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
+        info!("Deleting publish rule for {} on {}", id, platform);
+
+        let mut rules = state.publish_rules.write().unwrap();
+        match state.store.delete_publish_rule(platform, id) {
+            Ok(_) => {
+                rules.retain(|rule| !(rule.platform == *platform && rule.channel == *id));
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "message": format!("Rule for {} on {} deleted", id, platform)
+                    }))
+                )
+            },
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Failed to delete rule for {} on {}: {:?}", id, platform, e)
+                }))
+            ),
+        }
+    } else {
+        (StatusCode::BAD_REQUEST,
             Json(serde_json::json!({
                 "status": "error",
-                "message": format!("Failed to get messages: {:?}", e)
+                "message": "Missing platform or id parameter"
             }))
-        ),
+        )
     }
 }
 
-#[tokio::main]
-async fn main() {
-    tracing_subscriber::fmt()
-        .with_thread_ids(true)
-        .with_thread_names(true)
-        .init();
+async fn get_filters(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ViewerRole>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let filters: Vec<models::MessageFilter> = state.filters.read().unwrap()
+        .iter()
+        .map(|filter| filter.filter.clone())
+        .collect();
 
-    let conn = utils::initialize_db();
+    (StatusCode::OK,
+        Json(serde_json::json!({
+            "status": "success",
+            "filters": filters
+        }))
+    )
+}
 
-    let (admin_panel_sender, _) = broadcast::channel(1000);
-    let (client_sender, _) = broadcast::channel(1000);
+async fn add_filter(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Json(filter): Json<models::MessageFilter>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    info!("Adding filter: {:?}", filter);
 
-    let state = Arc::new(AppState {
-        db_conn: Arc::new(Mutex::new(conn)),
-        admin_panel_sender: admin_panel_sender.clone(),
-        client_sender: client_sender.clone(),
-        active_connections: AtomicUsize::new(0),
-        listened_channels: Arc::new(Mutex::new(HashMap::new())),
-    });
+    // Compile first so an invalid pattern is rejected before it is stored.
+    let mut compiled = match filters::CompiledFilter::new(filter) {
+        Ok(compiled) => compiled,
+        Err(e) => return (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Invalid filter: {}", e)
+            }))
+        ),
+    };
 
-    let all_channels = utils::get_channels(&state.db_conn.lock().unwrap()).expect("Failed to get channels");
-    for channel in all_channels {
-        if channel.listen {
-            match channel.platform.as_str() {
-                "twitch" => {
-                    let result = utils::listen_to_twitch(
-                        channel.name.clone(),
-                        admin_panel_sender.clone(),
-                        state.db_conn.clone(),
-                        state.listened_channels.clone(),
-                    ).await;
-
-                    if let Err(e) = result {
-                        warn!("Error starting Twitch listener for {}: {:?}", channel.name, e);
-                    } else {
-                        info!("Started Twitch listener for {}", channel.name);
-                    }
-                },
+    let mut filters = state.filters.write().unwrap();
+    match state.store.add_filter(&compiled.filter) {
+        Ok(id) => {
+            compiled.filter.id = Some(id);
+            let filter = compiled.filter.clone();
+            filters.push(compiled);
+            (StatusCode::OK,
+                Json(serde_json::json!({
+                    "status": "success",
+                    "filter": filter
+                }))
+            )
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to add filter: {:?}", e)
+            }))
+        ),
+    }
+}
 
-                "youtube" => {
-                    let result = utils::listen_to_youtube(
-                        channel.name.clone(),
-                        admin_panel_sender.clone(),
-                        state.db_conn.clone(),
-                        state.listened_channels.clone(),
-                    ).await;
-
-                    if let Err(e) = result {
-                        warn!("Error starting YouTube listener for {}: {:?}", channel.name, e);
-                    } else {
-                        info!("Started YouTube listener for {}", channel.name);
-                    }
-                },
+async fn delete_filter(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Path(id): Path<i64>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    info!("Deleting filter {}", id);
 
-                _ => {
-                    eprintln!("Unknown platform: {}", channel.platform);
-                }
-            }
-        }
+    let mut filters = state.filters.write().unwrap();
+    match state.store.delete_filter(id) {
+        Ok(_) => {
+            filters.retain(|filter| filter.filter.id != Some(id));
+            (StatusCode::OK,
+                Json(serde_json::json!({
+                    "status": "success",
+                    "message": format!("Filter {} deleted", id)
+                }))
+            )
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to delete filter {}: {:?}", id, e)
+            }))
+        ),
     }
+}
 
-    let args = parse_args();
+fn parse_user_list(params: &HashMap<String, String>) -> Result<models::UserList, (StatusCode, Json<serde_json::Value>)> {
+    params.get("list")
+        .ok_or_else(|| anyhow::anyhow!("Missing list parameter"))
+        .and_then(|list| list.parse::<models::UserList>())
+        .map_err(|e| (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": e.to_string()
+            }))
+        ))
+}
 
-    let app = Router::new()
-        .fallback_service(ServeDir::new("static"))
-        .route("/api/ws", any(client_ws_handler))
+async fn get_user_list(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ViewerRole>,
+    Path(params): Path<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let list = match parse_user_list(&params) {
+        Ok(list) => list,
+        Err(response) => return response,
+    };
+
+    let result = tokio::task::spawn_blocking(move || state.store.get_user_list(list)).await;
+    match result.map_err(anyhow::Error::from).and_then(|result| result) {
+        Ok(users) => (StatusCode::OK,
+            Json(serde_json::json!({
+                "status": "success",
+                "users": users
+            }))
+        ),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to get {:?} users: {:?}", list, e)
+            }))
+        ),
+    }
+}
+
+async fn add_user_to_list(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ModeratorRole>,
+    Path(params): Path<std::collections::HashMap<String, String>>,
+    Query(query): Query<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let list = match parse_user_list(&params) {
+        Ok(list) => list,
+        Err(response) => return response,
+    };
+
+    if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
+        info!("Adding user {} on {} to {:?} list", id, platform, list);
+
+        let entry = models::UserListEntry {
+            platform: platform.clone(),
+            user_id: id.clone(),
+            username: query.get("username").cloned(),
+            added_at: chrono::Utc::now().timestamp_millis() as u64,
+        };
+
+        let mut user_lists = state.user_lists.write().unwrap();
+        match state.store.add_user_to_list(list, &entry) {
+            Ok(_) => {
+                user_lists.get_mut(list).insert((platform.clone(), id.clone()));
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "user": entry
+                    }))
+                )
+            },
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Failed to add user {} on {}: {:?}", id, platform, e)
+                }))
+            ),
+        }
+    } else {
+        (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Missing platform or id parameter"
+            }))
+        )
+    }
+}
+
+async fn remove_user_from_list(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ModeratorRole>,
+    Path(params): Path<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let list = match parse_user_list(&params) {
+        Ok(list) => list,
+        Err(response) => return response,
+    };
+
+    if let (Some(platform), Some(id)) = (params.get("platform"), params.get("id")) {
+        info!("Removing user {} on {} from {:?} list", id, platform, list);
+
+        let mut user_lists = state.user_lists.write().unwrap();
+        match state.store.remove_user_from_list(list, platform, id) {
+            Ok(_) => {
+                user_lists.get_mut(list).remove(&(platform.clone(), id.clone()));
+                (StatusCode::OK,
+                    Json(serde_json::json!({
+                        "status": "success",
+                        "message": format!("User {} on {} removed", id, platform)
+                    }))
+                )
+            },
+            Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+                Json(serde_json::json!({
+                    "status": "error",
+                    "message": format!("Failed to remove user {} on {}: {:?}", id, platform, e)
+                }))
+            ),
+        }
+    } else {
+        (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Missing platform or id parameter"
+            }))
+        )
+    }
+}
+
+async fn get_listeners(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ViewerRole>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::OK,
+        Json(serde_json::json!({
+            "status": "success",
+            "listeners": listeners::get_listener_statuses(&state)
+        }))
+    )
+}
+
+async fn get_messages(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ViewerRole>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let limit = params.get("limit").and_then(|v| v.parse::<usize>().ok()).unwrap_or(50);
+    let before = params.get("before").and_then(|v| v.parse::<u64>().ok());
+
+    match state.store.get_messages(limit, before) {
+        Ok(messages) => {
+            let json_messages: Vec<serde_json::Value> = messages.iter().map(|msg| {
+                serde_json::json!({
+                    "id": msg.id.to_string(),
+                    "platform": msg.platform,
+                    "channel": msg.channel,
+                    "username": msg.username,
+                    "content": msg.content,
+                    "additional_info": msg.additional_info,
+                    "timestamp": msg.timestamp,
+                    "published": msg.published,
+                    "status": msg.status,
+                    "flags": msg.flags,
+                    "edited_content": msg.edited_content
+                })
+            }).collect();
+
+            info!("Retrieved {} messages", json_messages.len());
+
+            (StatusCode::OK, 
+                Json(serde_json::json!({
+                    "status": "success",
+                    "messages": json_messages
+                }))
+            )
+        },
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, 
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to get messages: {:?}", e)
+            }))
+        ),
+    }
+}
+
+/// Full-text search over the stored messages, best match first. Pass
+/// `next_cursor` back as `cursor` to get the next page.
+async fn search_messages(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ViewerRole>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let bad_request = |message: String| (StatusCode::BAD_REQUEST,
+        Json(serde_json::json!({
+            "status": "error",
+            "message": message
+        }))
+    );
+
+    let terms = store::search_terms(params.get("q").map(String::as_str).unwrap_or_default());
+    if terms.is_empty() {
+        return bad_request("Search needs at least one word".to_string());
+    }
+    let status = match params.get("status").map(|v| v.parse::<models::MessageStatus>()).transpose() {
+        Ok(status) => status,
+        Err(e) => return bad_request(e.to_string()),
+    };
+    let after = match params.get("cursor").map(|v| v.parse::<store::SearchCursor>()).transpose() {
+        Ok(after) => after,
+        Err(e) => return bad_request(e.to_string()),
+    };
+
+    let query = store::SearchQuery {
+        terms,
+        platform: params.get("platform").cloned(),
+        channel: params.get("channel").cloned(),
+        username: params.get("user").cloned(),
+        status,
+        limit: params.get("limit").and_then(|v| v.parse::<usize>().ok()).unwrap_or(50).min(500),
+        after,
+    };
+
+    match state.store.search_messages(&query) {
+        Ok(page) => (StatusCode::OK,
+            Json(serde_json::json!({
+                "status": "success",
+                "results": page.results,
+                "next_cursor": page.next_cursor.map(|cursor| cursor.to_string())
+            }))
+        ),
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to search messages: {:?}", e)
+            }))
+        ),
+    }
+}
+
+async fn get_audit_log(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let action = match params.get("action").map(|v| v.parse::<models::AuditAction>()).transpose() {
+        Ok(action) => action,
+        Err(e) => return (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": e.to_string()
+            }))
+        ),
+    };
+
+    let query = store::AuditQuery {
+        limit: params.get("limit").and_then(|v| v.parse::<usize>().ok()).unwrap_or(50).min(500),
+        before: params.get("before").and_then(|v| v.parse::<i64>().ok()),
+        actor: params.get("actor").cloned(),
+        action,
+        message_id: params.get("message_id").and_then(|v| v.parse::<models::MessageId>().ok()),
+    };
+
+    match state.store.get_audit_log(&query) {
+        Ok(entries) => {
+            // Pass as `before` to get the next page; null on the last page.
+            let next_before = entries.last().filter(|_| entries.len() == query.limit).and_then(|entry| entry.id);
+            (StatusCode::OK,
+                Json(serde_json::json!({
+                    "status": "success",
+                    "entries": entries,
+                    "next_before": next_before
+                }))
+            )
+        }
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to get audit log: {:?}", e)
+            }))
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct LoginRequest {
+    username: String,
+    password: String,
+}
+
+async fn login(
+    State(state): State<Arc<AppState>>,
+    Json(credentials): Json<LoginRequest>,
+) -> Response {
+    let account = state.store.get_account(&credentials.username);
+
+    match account {
+        Ok(Some(account)) if auth::verify_password(&credentials.password, &account.password_hash) => {
+            let token = state.sessions.create(&account.username, account.role);
+            info!("{} logged in as {}", account.username, account.role.as_str());
+            (
+                StatusCode::OK,
+                [(header::SET_COOKIE, state.sessions.cookie(&token))],
+                Json(serde_json::json!({
+                    "status": "success",
+                    "username": account.username,
+                    "role": account.role,
+                    "token": token
+                })),
+            ).into_response()
+        }
+
+        Ok(_) => {
+            warn!("Failed login for {}", credentials.username);
+            (StatusCode::UNAUTHORIZED,
+                Json(serde_json::json!({
+                    "status": "error",
+                    "message": "Invalid username or password"
+                }))
+            ).into_response()
+        }
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to log in: {:?}", e)
+            }))
+        ).into_response(),
+    }
+}
+
+async fn logout(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+) -> Response {
+    if let Some(token) = auth::request_token(&headers, None) {
+        state.sessions.remove(&token);
+    }
+
+    (
+        StatusCode::OK,
+        [(header::SET_COOKIE, auth::expired_session_cookie())],
+        Json(serde_json::json!({
+            "status": "success"
+        })),
+    ).into_response()
+}
+
+async fn get_session(auth::Authorized(session, _): auth::Authorized<auth::ViewerRole>) -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::OK, Json(serde_json::json!({
+        "status": "success",
+        "username": session.username,
+        "role": session.role,
+        "expires_at": session.expires_at
+    })))
+}
+
+#[derive(serde::Deserialize)]
+struct ChangePasswordRequest {
+    current_password: String,
+    new_password: String,
+}
+
+async fn change_password(
+    State(state): State<Arc<AppState>>,
+    auth::Authorized(session, _): auth::Authorized<auth::ViewerRole>,
+    Json(request): Json<ChangePasswordRequest>,
+) -> Response {
+    if request.new_password.is_empty() {
+        return (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "New password must not be empty"
+            }))
+        ).into_response();
+    }
+
+    let result = {
+        let _order = state.event_order.lock().unwrap();
+        match state.store.get_account(&session.username) {
+            Ok(Some(mut account)) if auth::verify_password(&request.current_password, &account.password_hash) => {
+                auth::hash_password(&request.new_password)
+                    .and_then(|hash| {
+                        account.password_hash = hash;
+                        state.store.save_account(&account)
+                    })
+                    .map(|_| true)
+            }
+            Ok(_) => Ok(false),
+            Err(e) => Err(e),
+        }
+    };
+
+    match result {
+        Ok(true) => {
+            // Log out everywhere else; the caller gets a fresh session.
+            state.sessions.remove_user(&session.username);
+            let token = state.sessions.create(&session.username, session.role);
+            (
+                StatusCode::OK,
+                [(header::SET_COOKIE, state.sessions.cookie(&token))],
+                Json(serde_json::json!({
+                    "status": "success",
+                    "token": token
+                })),
+            ).into_response()
+        }
+
+        Ok(false) => (StatusCode::UNAUTHORIZED,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Current password is wrong"
+            }))
+        ).into_response(),
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to change password: {:?}", e)
+            }))
+        ).into_response(),
+    }
+}
+
+async fn get_overlay_token(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::ModeratorRole>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    (StatusCode::OK, Json(serde_json::json!({
+        "status": "success",
+        "token": *state.overlay_token.read().unwrap()
+    })))
+}
+
+/// Replaces the overlay token, locking out overlays that still use the old one
+/// once they reconnect.
+async fn rotate_overlay_token(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let token = auth::generate_token();
+    match state.store.set_setting(auth::OVERLAY_TOKEN_SETTING, &token) {
+        Ok(()) => {
+            *state.overlay_token.write().unwrap() = token.clone();
+            (StatusCode::OK, Json(serde_json::json!({
+                "status": "success",
+                "token": token
+            })))
+        }
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to rotate overlay token: {:?}", e)
+            }))
+        ),
+    }
+}
+
+/// Enforces the retention limits right away instead of waiting for the next
+/// scheduled run. `?vacuum=true` also compacts the database.
+async fn prune_messages(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Query(params): Query<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let vacuum = params.get("vacuum").is_some_and(|vacuum| vacuum == "true");
+    let result = tokio::task::spawn_blocking(move || retention::prune(&state, vacuum)).await;
+    match result.map_err(anyhow::Error::from).and_then(|result| result) {
+        Ok((deleted, vacuumed)) => (StatusCode::OK,
+            Json(serde_json::json!({
+                "status": "success",
+                "deleted": deleted,
+                "vacuumed": vacuumed
+            }))
+        ),
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to prune messages: {:?}", e)
+            }))
+        ),
+    }
+}
+
+async fn get_accounts(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    match state.store.get_accounts() {
+        Ok(accounts) => (StatusCode::OK,
+            Json(serde_json::json!({
+                "status": "success",
+                "accounts": accounts
+            }))
+        ),
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to get accounts: {:?}", e)
+            }))
+        ),
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct AccountRequest {
+    password: Option<String>,
+    role: Option<models::Role>,
+}
+
+/// Creates an account, or changes the password or role of an existing one.
+/// The account's sessions end so a new role takes effect right away.
+async fn save_account(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Path(params): Path<std::collections::HashMap<String, String>>,
+    Json(request): Json<AccountRequest>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(username) = params.get("username") else {
+        return (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Missing username parameter"
+            }))
+        );
+    };
+
+    if request.password.as_deref().is_some_and(str::is_empty) {
+        return (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Password must not be empty"
+            }))
+        );
+    }
+
+    let result = (|| -> anyhow::Result<Result<models::Account, &'static str>> {
+        // Held so two requests cannot both take away the last owner.
+        let _order = state.event_order.lock().unwrap();
+        let mut account = match state.store.get_account(username)? {
+            Some(account) => account,
+            None if request.password.is_none() => return Ok(Err("A new account needs a password")),
+            None => models::Account {
+                username: username.clone(),
+                password_hash: String::new(),
+                role: models::Role::Viewer,
+                created_at: chrono::Utc::now().timestamp_millis() as u64,
+            },
+        };
+
+        if let Some(role) = request.role {
+            if account.role == models::Role::Owner && role != models::Role::Owner
+                && state.store.count_accounts(Some(models::Role::Owner))? <= 1 {
+                return Ok(Err("Cannot demote the last owner"));
+            }
+            account.role = role;
+        }
+        if let Some(password) = &request.password {
+            account.password_hash = auth::hash_password(password)?;
+        }
+
+        state.store.save_account(&account)?;
+        Ok(Ok(account))
+    })();
+
+    match result {
+        Ok(Ok(account)) => {
+            state.sessions.remove_user(&account.username);
+            info!("Saved account {} as {}", account.username, account.role.as_str());
+            (StatusCode::OK,
+                Json(serde_json::json!({
+                    "status": "success",
+                    "account": account
+                }))
+            )
+        }
+
+        Ok(Err(message)) => (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": message
+            }))
+        ),
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to save account: {:?}", e)
+            }))
+        ),
+    }
+}
+
+async fn delete_account(
+    State(state): State<Arc<AppState>>,
+    _: auth::Authorized<auth::OwnerRole>,
+    Path(params): Path<std::collections::HashMap<String, String>>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let Some(username) = params.get("username") else {
+        return (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Missing username parameter"
+            }))
+        );
+    };
+
+    let result = (|| -> anyhow::Result<bool> {
+        // Held so two requests cannot both take away the last owner.
+        let _order = state.event_order.lock().unwrap();
+        let is_owner = state.store.get_account(username)?.is_some_and(|account| account.role == models::Role::Owner);
+        if is_owner && state.store.count_accounts(Some(models::Role::Owner))? <= 1 {
+            return Ok(false);
+        }
+        state.store.delete_account(username)?;
+        Ok(true)
+    })();
+
+    match result {
+        Ok(true) => {
+            state.sessions.remove_user(username);
+            info!("Deleted account {}", username);
+            (StatusCode::OK,
+                Json(serde_json::json!({
+                    "status": "success",
+                    "message": format!("Deleted account {}", username)
+                }))
+            )
+        }
+
+        Ok(false) => (StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": "Cannot delete the last owner"
+            }))
+        ),
+
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR,
+            Json(serde_json::json!({
+                "status": "error",
+                "message": format!("Failed to delete account: {:?}", e)
+            }))
+        ),
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    tracing_subscriber::fmt()
+        .with_thread_ids(true)
+        .with_thread_names(true)
+        .init();
+
+    let config = config::Config::load().expect("Invalid configuration");
+
+    let store = store::open(&config.database).expect("Failed to open message store");
+    let publish_rules = store.get_publish_rules().expect("Failed to load publish rules");
+    let message_filters = store.get_filters()
+        .and_then(|stored| filters::load(config.filters.rules.clone(), stored))
+        .expect("Failed to load message filters");
+    let user_lists = utils::get_user_lists(&*store).expect("Failed to load user lists");
+    let overlay_token = auth::bootstrap(&*store, config.auth.admin_password.as_deref(), config.auth.reset_admin).expect("Failed to set up authentication");
+
+    let (client_sender, _) = broadcast::channel(config.server.broadcast_capacity);
+    let (message_writer, write_queue) = db::MessageWriter::new(config.database.write_queue);
+
+    let state = Arc::new(AppState {
+        event_order: Mutex::new(()),
+        store,
+        message_writer,
+        admin_panel_sender: events::AdminBroadcaster::new(config.server.broadcast_capacity),
+        client_sender: client_sender.clone(),
+        active_connections: AtomicUsize::new(0),
+        listened_channels: Arc::new(Mutex::new(HashMap::new())),
+        sources: sources::SourceRegistry::new(),
+        publish_rules: RwLock::new(publish_rules),
+        filters: RwLock::new(message_filters),
+        user_lists: RwLock::new(user_lists),
+        pinned_message: Mutex::new(None),
+        screen_cleared_at: AtomicU64::new(0),
+        sessions: auth::Sessions::new(config.auth.session_ttl_hours * 60 * 60 * 1000),
+        overlay_token: RwLock::new(overlay_token),
+        config,
+    });
+
+    db::spawn_writer(state.clone(), write_queue, state.config.database.max_batch);
+    tokio::spawn(retention::run(state.clone()));
+
+    let all_channels = state.store.get_channels().expect("Failed to get channels");
+    for channel in all_channels {
+        if channel.listen && state.config.listeners.restore_on_start {
+            let result = listeners::listen_to_channel(state.clone(), &channel.platform, &channel.name);
+
+            if let Err(e) = result {
+                warn!("Error starting {} listener for {}: {:?}", channel.platform, channel.name, e);
+            } else {
+                info!("Started {} listener for {}", channel.platform, channel.name);
+            }
+        }
+    }
+
+    let admin_routes = Router::new()
         .route("/api/admin/ws", any(admin_ws_handler))
-        
         .route("/api/messages", get(get_messages))
+        .route("/api/search", get(search_messages))
         .route("/api/publish/{id}", post(publish_message))
+        .route("/api/reject/{id}", post(reject_message))
+        .route("/api/unpublish/{id}", post(unpublish_message))
+        .route("/api/edit/{id}", post(edit_message))
+        .route("/api/confirm", post(confirm_message))
         
         .route("/api/channels", get(get_channels))
         .route("/api/channels/{platform}/{id}", post(add_channel))
@@ -447,18 +1568,232 @@ async fn main() {
         
         .route("/api/listen/{platform}/{id}", post(listen_channel))
         .route("/api/unlisten/{platform}/{id}", post(unlisten_channel))
+        .route("/api/listeners", get(get_listeners))
+
+        .route("/api/rules", get(get_rules))
+        .route("/api/rules/{platform}/{id}", post(save_rule))
+        .route("/api/rules/{platform}/{id}", delete(delete_rule))
+
+        .route("/api/filters", get(get_filters))
+        .route("/api/filters", post(add_filter))
+        .route("/api/filters/{id}", delete(delete_filter))
+
+        .route("/api/users/{list}", get(get_user_list))
+        .route("/api/users/{list}/{platform}/{id}", post(add_user_to_list))
+        .route("/api/users/{list}/{platform}/{id}", delete(remove_user_from_list))
+
+        .route("/api/auth/session", get(get_session))
+        .route("/api/auth/password", post(change_password))
+        .route("/api/auth/overlay-token", get(get_overlay_token))
+        .route("/api/auth/overlay-token", post(rotate_overlay_token))
+
+        .route("/api/audit", get(get_audit_log))
+
+        .route("/api/maintenance/prune", post(prune_messages))
 
+        .route("/api/accounts", get(get_accounts))
+        .route("/api/accounts/{username}", post(save_account))
+        .route("/api/accounts/{username}", delete(delete_account))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_session));
+
+    let allowed_origins = state.config.server.allowed_origins.iter()
+        .filter_map(|origin| origin.parse().ok())
+        .collect::<Vec<_>>();
+
+    let address = (state.config.server.host.clone(), state.config.server.port);
+    let app = Router::new()
+        .fallback_service(ServeDir::new(&state.config.server.static_dir))
+        .route("/api/ws", any(client_ws_handler))
+        .route("/api/auth/login", post(login))
+        .route("/api/auth/logout", post(logout))
+        .merge(admin_routes)
         .layer(
             CorsLayer::new()
-                .allow_methods(Any)
-                .allow_headers(Any)
-                .allow_origin(Any),
+                .allow_methods([Method::GET, Method::POST, Method::DELETE])
+                .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+                .allow_credentials(true)
+                .allow_origin(AllowOrigin::list(allowed_origins)),
         )
         .with_state(state);
     
-    tracing::info!("Server running on {}:{}", args.host, args.port);
-    let listener = tokio::net::TcpListener::bind((args.host, args.port)).await
+    tracing::info!("Server running on {}:{}", address.0, address.1);
+    let listener = tokio::net::TcpListener::bind(address).await
         .expect("Failed to bind TCP listener");
 
     axum::serve(listener, app).await.unwrap();
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::marker::PhantomData;
+
+    use super::*;
+    use crate::models::{ChatMessage, MessageId, MessageStatus, Role};
+    use crate::protocol::{AdminCommand, AdminRequest, ClientEvent};
+
+    /// App state on a `MemoryStore`.
+    fn test_state() -> Arc<AppState> {
+        let config = config::Config::default();
+        let (message_writer, _) = db::MessageWriter::new(1);
+
+        Arc::new(AppState {
+            event_order: Mutex::new(()),
+            store: Box::new(store::MemoryStore::default()),
+            message_writer,
+            admin_panel_sender: events::AdminBroadcaster::new(100),
+            client_sender: broadcast::channel(100).0,
+            active_connections: AtomicUsize::new(0),
+            listened_channels: Arc::new(Mutex::new(HashMap::new())),
+            sources: sources::SourceRegistry::new(),
+            publish_rules: RwLock::new(Vec::new()),
+            filters: RwLock::new(Vec::new()),
+            user_lists: RwLock::new(Default::default()),
+            pinned_message: Mutex::new(None),
+            screen_cleared_at: AtomicU64::new(0),
+            sessions: auth::Sessions::new(60 * 60 * 1000),
+            overlay_token: RwLock::new("token".to_string()),
+            config,
+        })
+    }
+
+    fn session(role: Role) -> auth::Session {
+        auth::Session { username: "mod".to_string(), role, expires_at: u64::MAX }
+    }
+
+    fn authorized<R>(role: Role) -> auth::Authorized<R> {
+        auth::Authorized(session(role), PhantomData)
+    }
+
+    fn pending_message(state: &AppState, content: &str) -> MessageId {
+        let message = ChatMessage {
+            id: MessageId::new(),
+            platform: "twitch".to_string(),
+            channel: "a".to_string(),
+            username: "alice".to_string(),
+            content: content.to_string(),
+            additional_info: None,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            published: false,
+            status: MessageStatus::Pending,
+            flags: Vec::new(),
+            edited_content: None,
+            published_at: None,
+        };
+        state.store.insert_message(&message).unwrap();
+        message.id
+    }
+
+    async fn publish(state: &Arc<AppState>, id: MessageId) -> StatusCode {
+        publish_message(State(state.clone()), authorized(Role::Moderator), Path(id)).await.0
+    }
+
+    async fn unpublish(state: &Arc<AppState>, id: MessageId) -> StatusCode {
+        unpublish_message(State(state.clone()), authorized(Role::Moderator), Path(id)).await.0
+    }
+
+    async fn reject(state: &Arc<AppState>, id: MessageId) -> StatusCode {
+        reject_message(State(state.clone()), authorized(Role::Moderator), Path(id)).await.0
+    }
+
+    fn command(state: &Arc<AppState>, command: AdminCommand) {
+        let reply = commands::handle_command(state, &session(Role::Moderator), AdminRequest { request_id: None, command }, &Arc::new(Mutex::new(None)));
+        assert!(matches!(reply, protocol::AdminEvent::Ack { .. }), "{:?}", reply);
+    }
+
+    #[tokio::test]
+    async fn status_handlers_follow_the_allowed_transitions() {
+        let state = test_state();
+        let mut overlay = state.client_sender.subscribe();
+        let id = pending_message(&state, "hello");
+
+        assert_eq!(unpublish(&state, id).await, StatusCode::CONFLICT);
+        assert_eq!(publish(&state, id).await, StatusCode::OK);
+        assert_eq!(publish(&state, id).await, StatusCode::CONFLICT);
+        assert_eq!(unpublish(&state, id).await, StatusCode::OK);
+        assert_eq!(reject(&state, id).await, StatusCode::OK);
+        assert_eq!(unpublish(&state, id).await, StatusCode::CONFLICT);
+        assert_eq!(publish(&state, MessageId::new()).await, StatusCode::NOT_FOUND);
+
+        assert!(matches!(overlay.try_recv(), Ok(ClientEvent::MessagePublished(message)) if message.id == id));
+        assert!(matches!(overlay.try_recv(), Ok(ClientEvent::MessageRetracted { id: retracted }) if retracted == id));
+        assert!(overlay.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn edit_handler_maps_errors_to_status_codes() {
+        let state = test_state();
+        let id = pending_message(&state, "hello");
+        let edit = |id, content: Option<&str>| edit_message(
+            State(state.clone()),
+            authorized(Role::Moderator),
+            Path(id),
+            Json(EditRequest { content: content.map(str::to_string) }),
+        );
+
+        assert_eq!(edit(id, Some("fixed")).await.0, StatusCode::OK);
+        assert_eq!(edit(id, Some("  ")).await.0, StatusCode::BAD_REQUEST);
+        assert_eq!(edit(MessageId::new(), Some("fixed")).await.0, StatusCode::NOT_FOUND);
+        assert_eq!(edit(id, None).await.0, StatusCode::OK);
+        assert_eq!(state.store.get_message(id).unwrap().unwrap().edited_content, None);
+    }
+
+    #[tokio::test]
+    async fn rejecting_a_pinned_message_retracts_and_unpins_it() {
+        let state = test_state();
+        let id = pending_message(&state, "hello");
+        assert_eq!(publish(&state, id).await, StatusCode::OK);
+        command(&state, AdminCommand::Pin { id });
+
+        let mut overlay = state.client_sender.subscribe();
+        assert_eq!(reject(&state, id).await, StatusCode::OK);
+
+        assert!(matches!(overlay.try_recv(), Ok(ClientEvent::MessageRetracted { id: retracted }) if retracted == id));
+        assert!(matches!(overlay.try_recv(), Ok(ClientEvent::MessageUnpinned { id: unpinned }) if unpinned == id));
+        assert!(state.pinned_message.lock().unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn flagged_messages_from_trusted_users_stay_pending() {
+        let state = test_state();
+        let [flagged, clean] = [pending_message(&state, "flagged"), pending_message(&state, "clean")];
+
+        for (id, flags) in [(flagged, vec!["links".to_string()]), (clean, Vec::new())] {
+            let mut message = state.store.get_message(id).unwrap().unwrap();
+            message.flags = flags;
+            listeners::apply_rules(&state, &db::QueuedMessage { message, user_list: Some(models::UserList::Trusted) });
+        }
+
+        assert_eq!(state.store.get_message(flagged).unwrap().unwrap().status, MessageStatus::Pending);
+        assert_eq!(state.store.get_message(clean).unwrap().unwrap().status, MessageStatus::Published);
+    }
+
+    #[tokio::test]
+    async fn overlay_backlog_starts_at_the_last_clear() {
+        let state = test_state();
+        let [before_clear, received_before_clear] = [pending_message(&state, "one"), pending_message(&state, "two")];
+        assert_eq!(publish(&state, before_clear).await, StatusCode::OK);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        command(&state, AdminCommand::ClearScreen);
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert_eq!(publish(&state, received_before_clear).await, StatusCode::OK);
+
+        let (_, replay) = client_catch_up(&state, 10, None);
+        let replayed: Vec<_> = replay.iter().filter_map(|event| match event {
+            ClientEvent::MessagePublished(message) => Some(message.id),
+            _ => None,
+        }).collect();
+        assert_eq!(replayed, vec![received_before_clear]);
+    }
+
+    #[tokio::test]
+    async fn overlay_token_round_trips_through_the_store() {
+        let state = test_state();
+        let (status, Json(body)) = rotate_overlay_token(State(state.clone()), authorized(Role::Owner)).await;
+        assert_eq!(status, StatusCode::OK);
+        let token = body["token"].as_str().unwrap().to_string();
+
+        assert_eq!(state.store.get_setting(auth::OVERLAY_TOKEN_SETTING).unwrap(), Some(token.clone()));
+        let (_, Json(body)) = get_overlay_token(State(state.clone()), authorized(Role::Moderator)).await;
+        assert_eq!(body["token"], token.as_str());
+    }
+}
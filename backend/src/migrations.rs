@@ -0,0 +1,402 @@
+//! Schema migrations, tracked through SQLite's `PRAGMA user_version`.
+//!
+//! Each migration runs in its own transaction together with the version bump,
+//! so a failed upgrade leaves the database at the last good version. Databases
+//! created before versioning report version 0 and go through every migration;
+//! the first one only creates what is missing, so it is safe on any of them.
+
+use tracing::info;
+
+use crate::models::UserList;
+
+type Migration = fn(&rusqlite::Transaction) -> rusqlite::Result<()>;
+
+/// Every migration in order. The schema version is the number of migrations applied,
+/// so never reorder or remove entries, only append.
+const MIGRATIONS: &[Migration] = &[
+    baseline,
+    add_keys_and_indexes,
+    canonical_message_ids,
+    add_message_search,
+    add_published_at,
+];
+
+/// Brings the database up to the latest schema version.
+pub fn run(conn: &mut rusqlite::Connection) -> anyhow::Result<()> {
+    let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    if version > MIGRATIONS.len() {
+        return Err(anyhow::anyhow!(
+            "Database schema version {} is newer than this build supports ({})",
+            version,
+            MIGRATIONS.len()
+        ));
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let target = index + 1;
+        info!("Migrating database to schema version {}", target);
+        let tx = conn.transaction()?;
+        migration(&tx)
+            .map_err(|e| anyhow::anyhow!("Migration to schema version {} failed: {}", target, e))?;
+        tx.pragma_update(None, "user_version", target)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Adds a column to a table created by an older version of the schema.
+/// Returns whether the column had to be added.
+fn add_column_if_missing(conn: &rusqlite::Connection, table: &str, column: &str, definition: &str) -> rusqlite::Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<rusqlite::Result<Vec<_>>>()?
+        .iter()
+        .any(|name| name == column);
+
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(!exists)
+}
+
+/// The schema as it was built before versioning, including the columns older
+/// databases gained one by one.
+fn baseline(conn: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS messages (
+            id BLOB,
+            platform TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            username TEXT NOT NULL,
+            content TEXT NOT NULL,
+            additional_info TEXT,
+            timestamp INTEGER NOT NULL,
+            published INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL DEFAULT 'pending',
+            unpublished_by TEXT,
+            unpublished_at INTEGER,
+            flags TEXT
+        )",
+        [],
+    )?;
+
+    add_column_if_missing(conn, "messages", "unpublished_by", "TEXT")?;
+    add_column_if_missing(conn, "messages", "unpublished_at", "INTEGER")?;
+    add_column_if_missing(conn, "messages", "flags", "TEXT")?;
+    add_column_if_missing(conn, "messages", "edited_content", "TEXT")?;
+
+    if add_column_if_missing(conn, "messages", "status", "TEXT NOT NULL DEFAULT 'pending'")? {
+        conn.execute("UPDATE messages SET status = 'published' WHERE published = 1", [])?;
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS channels (
+            id blob PRIMARY KEY,
+            name TEXT NOT NULL,
+            platform TEXT NOT NULL,
+            listen INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT
+        )",
+        [],
+    )?;
+
+    add_column_if_missing(conn, "channels", "last_error", "TEXT")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS publish_rules (
+            platform TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            rule TEXT NOT NULL,
+            PRIMARY KEY (platform, channel)
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS message_filters (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            filter TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    for list in [UserList::Trusted, UserList::Blocked] {
+        conn.execute(
+            &format!("CREATE TABLE IF NOT EXISTS {} (
+                platform TEXT NOT NULL,
+                user_id TEXT NOT NULL,
+                username TEXT,
+                added_at INTEGER NOT NULL,
+                PRIMARY KEY (platform, user_id)
+            )", list.table()),
+            [],
+        )?;
+    }
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS accounts (
+            username TEXT PRIMARY KEY,
+            password_hash TEXT NOT NULL,
+            created_at INTEGER NOT NULL
+        )",
+        [],
+    )?;
+    // Accounts from before roles existed were all full admins.
+    add_column_if_missing(conn, "accounts", "role", "TEXT NOT NULL DEFAULT 'owner'")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL,
+            actor TEXT NOT NULL,
+            action TEXT NOT NULL,
+            message_id BLOB,
+            platform TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            before TEXT,
+            after TEXT
+        )",
+        [],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    Ok(())
+}
+
+/// Rebuilds messages with a primary key on id and channels with a unique
+/// (platform, name), and indexes the columns messages are looked up by.
+///
+/// Duplicate messages keep the first copy stored. Duplicate channels keep the
+/// one being listened to, otherwise the first one added.
+fn add_keys_and_indexes(conn: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE messages_new (
+            id BLOB NOT NULL PRIMARY KEY,
+            platform TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            username TEXT NOT NULL,
+            content TEXT NOT NULL,
+            additional_info TEXT,
+            timestamp INTEGER NOT NULL,
+            published INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL DEFAULT 'pending',
+            unpublished_by TEXT,
+            unpublished_at INTEGER,
+            flags TEXT,
+            edited_content TEXT
+        );
+        INSERT OR IGNORE INTO messages_new
+            (id, platform, channel, username, content, additional_info, timestamp, published, status, unpublished_by, unpublished_at, flags, edited_content)
+            SELECT id, platform, channel, username, content, additional_info, timestamp, published, status, unpublished_by, unpublished_at, flags, edited_content
+            FROM messages WHERE id IS NOT NULL ORDER BY rowid;
+        DROP TABLE messages;
+        ALTER TABLE messages_new RENAME TO messages;
+
+        CREATE INDEX messages_timestamp ON messages (timestamp);
+        CREATE INDEX messages_channel ON messages (platform, channel, timestamp);
+        CREATE INDEX messages_username ON messages (platform, username);
+        CREATE INDEX messages_status ON messages (status, timestamp);
+
+        CREATE TABLE channels_new (
+            id BLOB NOT NULL PRIMARY KEY,
+            name TEXT NOT NULL,
+            platform TEXT NOT NULL,
+            listen INTEGER NOT NULL DEFAULT 0,
+            last_error TEXT,
+            UNIQUE (platform, name)
+        );
+        INSERT OR IGNORE INTO channels_new (id, name, platform, listen, last_error)
+            SELECT id, name, platform, listen, last_error
+            FROM channels WHERE id IS NOT NULL ORDER BY listen DESC, rowid;
+        DROP TABLE channels;
+        ALTER TABLE channels_new RENAME TO channels;
+
+        CREATE INDEX audit_log_message_id ON audit_log (message_id);",
+    )
+}
+
+/// Message ids used to be stored as little-endian `u128` bytes while channel
+/// ids were already in UUID byte order. Reverses the message ids, including the
+/// ones referenced by the audit log, so every id uses the UUID order.
+fn canonical_message_ids(conn: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    for (table, column) in [("messages", "id"), ("audit_log", "message_id")] {
+        let rows = conn
+            .prepare(&format!("SELECT rowid, {} FROM {} WHERE length({}) = 16", column, table, column))?
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        let mut update = conn.prepare(&format!("UPDATE {} SET {} = ?1 WHERE rowid = ?2", table, column))?;
+        for (rowid, mut id) in rows {
+            id.reverse();
+            update.execute(rusqlite::params![id, rowid])?;
+        }
+    }
+    Ok(())
+}
+
+/// Indexes message content and usernames for full-text search. The index reads
+/// the text from messages and triggers keep it in sync with every change.
+fn add_message_search(conn: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE VIRTUAL TABLE messages_fts USING fts5 (
+            content,
+            username,
+            content = 'messages',
+            tokenize = 'unicode61 remove_diacritics 2'
+        );
+        INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+
+        CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+            INSERT INTO messages_fts (rowid, content, username) VALUES (new.rowid, new.content, new.username);
+        END;
+        CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+            INSERT INTO messages_fts (messages_fts, rowid, content, username) VALUES ('delete', old.rowid, old.content, old.username);
+        END;
+        CREATE TRIGGER messages_fts_update AFTER UPDATE OF content, username ON messages BEGIN
+            INSERT INTO messages_fts (messages_fts, rowid, content, username) VALUES ('delete', old.rowid, old.content, old.username);
+            INSERT INTO messages_fts (rowid, content, username) VALUES (new.rowid, new.content, new.username);
+        END;",
+    )
+}
+
+/// Records when each message was published, so overlays replay messages in the
+/// order they went on screen. Messages published before this migration are
+/// taken to have been published when they were received.
+fn add_published_at(conn: &rusqlite::Transaction) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE messages ADD COLUMN published_at INTEGER;
+        UPDATE messages SET published_at = timestamp WHERE status = 'published';
+        CREATE INDEX messages_published_at ON messages (status, published_at);",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tables as a database created before versioning had them, before the
+    /// status and flag columns were added.
+    const UNVERSIONED_SCHEMA: &str = "
+        CREATE TABLE messages (
+            id BLOB,
+            platform TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            username TEXT NOT NULL,
+            content TEXT NOT NULL,
+            additional_info TEXT,
+            timestamp INTEGER NOT NULL,
+            published INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE channels (
+            id blob PRIMARY KEY,
+            name TEXT NOT NULL,
+            platform TEXT NOT NULL,
+            listen INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE TABLE audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL,
+            actor TEXT NOT NULL,
+            action TEXT NOT NULL,
+            message_id BLOB,
+            platform TEXT NOT NULL,
+            channel TEXT NOT NULL,
+            before TEXT,
+            after TEXT
+        );";
+
+    fn unversioned_db() -> rusqlite::Connection {
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+        conn.execute_batch(UNVERSIONED_SCHEMA).unwrap();
+        conn
+    }
+
+    fn insert_message(conn: &rusqlite::Connection, id: Option<&[u8]>, content: &str, timestamp: i64, published: bool) {
+        conn.execute(
+            "INSERT INTO messages (id, platform, channel, username, content, timestamp, published)
+             VALUES (?1, 'twitch', 'a', 'alice', ?2, ?3, ?4)",
+            rusqlite::params![id, content, timestamp, published as i32],
+        ).unwrap();
+    }
+
+    fn user_version(conn: &rusqlite::Connection) -> usize {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn migrates_an_unversioned_database_with_duplicates() {
+        let mut conn = unversioned_db();
+        insert_message(&conn, Some(&[1; 16]), "first copy", 100, false);
+        insert_message(&conn, Some(&[1; 16]), "second copy", 100, false);
+        insert_message(&conn, Some(&[2; 16]), "published", 200, true);
+        insert_message(&conn, None, "no id", 300, false);
+        for (id, platform, name, listen) in [(1u8, "twitch", "a", false), (2, "twitch", "a", true), (3, "twitch", "a", false), (4, "youtube", "a", false)] {
+            conn.execute(
+                "INSERT INTO channels (id, name, platform, listen) VALUES (?1, ?2, ?3, ?4)",
+                rusqlite::params![[id; 16], name, platform, listen],
+            ).unwrap();
+        }
+
+        run(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+
+        let messages: Vec<(Vec<u8>, String, String, Option<i64>)> = conn
+            .prepare("SELECT id, content, status, published_at FROM messages ORDER BY timestamp").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(messages, vec![
+            (vec![1; 16], "first copy".to_string(), "pending".to_string(), None),
+            (vec![2; 16], "published".to_string(), "published".to_string(), Some(200)),
+        ]);
+
+        let channels: Vec<(Vec<u8>, String)> = conn
+            .prepare("SELECT id, platform FROM channels ORDER BY platform").unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).unwrap()
+            .collect::<rusqlite::Result<_>>().unwrap();
+        assert_eq!(channels, vec![(vec![2; 16], "twitch".to_string()), (vec![4; 16], "youtube".to_string())]);
+
+        // A second run finds nothing left to do.
+        run(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+        let count: i64 = conn.query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn reencodes_little_endian_message_ids() {
+        let decimal = "1512366075204170929049582354406559215";
+        let id: crate::models::MessageId = decimal.parse().unwrap();
+        let stored = decimal.parse::<u128>().unwrap().to_le_bytes();
+
+        let mut conn = unversioned_db();
+        insert_message(&conn, Some(&stored), "hello", 100, true);
+        conn.execute(
+            "INSERT INTO audit_log (timestamp, actor, action, message_id, platform, channel) VALUES (100, 'admin', 'publish', ?1, 'twitch', 'a')",
+            rusqlite::params![stored],
+        ).unwrap();
+
+        run(&mut conn).unwrap();
+        assert_eq!(user_version(&conn), MIGRATIONS.len());
+
+        let message_id: crate::models::MessageId = conn.query_row("SELECT id FROM messages", [], |row| row.get(0)).unwrap();
+        let audit_id: crate::models::MessageId = conn.query_row("SELECT message_id FROM audit_log", [], |row| row.get(0)).unwrap();
+        assert_eq!(message_id, id);
+        assert_eq!(audit_id, id);
+        assert_eq!(message_id.to_string(), "01234567-89ab-cdef-0123-456789abcdef");
+    }
+
+    #[test]
+    fn refuses_a_newer_schema() {
+        let mut conn = unversioned_db();
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() + 1).unwrap();
+        assert!(run(&mut conn).is_err());
+    }
+}
@@ -1,11 +1,86 @@
-use std::{collections::HashMap, sync::{atomic::AtomicUsize, Arc, Mutex}};
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicU64, AtomicUsize}, Arc, Mutex, RwLock}};
 
 use clap::Parser;
 use tokio::sync::broadcast;
 
+use crate::{auth::Sessions, config::Config, db::MessageWriter, events::AdminBroadcaster, filters::CompiledFilter, protocol::ClientEvent, sources::SourceRegistry, store::ChatStore};
+
+/// Defines an id wrapping a UUID. In the database it is the UUID's 16 bytes in
+/// RFC 4122 order, in JSON and URLs its hyphenated text.
+macro_rules! uuid_id {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub struct $name(uuid::Uuid);
+
+        impl $name {
+            /// A new time-ordered id.
+            pub fn new() -> Self {
+                Self(uuid::Uuid::now_v7())
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.hyphenated().fmt(f)
+            }
+        }
+
+        /// Also accepts the decimal form ids were sent in before they had a type.
+        impl std::str::FromStr for $name {
+            type Err = anyhow::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                uuid::Uuid::parse_str(s)
+                    .or_else(|_| s.parse::<u128>().map(uuid::Uuid::from_u128))
+                    .map(Self)
+                    .map_err(|_| anyhow::anyhow!("Invalid id: {}", s))
+            }
+        }
+
+        impl serde::Serialize for $name {
+            fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> serde::Deserialize<'de> for $name {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+
+        impl rusqlite::types::ToSql for $name {
+            fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+                Ok(rusqlite::types::ToSqlOutput::Borrowed(rusqlite::types::ValueRef::Blob(self.0.as_bytes())))
+            }
+        }
+
+        impl rusqlite::types::FromSql for $name {
+            fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+                let bytes = value.as_blob()?;
+                uuid::Uuid::from_slice(bytes)
+                    .map(Self)
+                    .map_err(|_| rusqlite::types::FromSqlError::InvalidBlobSize { expected_size: 16, blob_size: bytes.len() })
+            }
+        }
+    };
+}
+
+uuid_id!(
+    /// Identifies a chat message, assigned when the message is received.
+    MessageId
+);
+
+uuid_id!(
+    /// Identifies a stored channel.
+    ChannelId
+);
+
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct ChatMessage {
-    pub id: String,
+    pub id: MessageId,
     pub platform: String,
     pub channel: String,
     pub username: String,
@@ -13,38 +88,475 @@ pub struct ChatMessage {
     pub additional_info: Option<String>,
     pub timestamp: u64,
     pub published: bool,
+    pub status: MessageStatus,
+    /// Reasons a filter flagged this message for the moderator.
+    #[serde(default)]
+    pub flags: Vec<String>,
+    /// Text set by a moderator to show instead of `content`, which keeps the original.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_content: Option<String>,
+    /// When the message was last published, unlike `timestamp`, which is when
+    /// it was received. `None` if it never was.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<u64>,
+}
+
+impl ChatMessage {
+    /// The message as overlays see it: the edited text, if any, as its content.
+    pub fn for_overlay(&self) -> ChatMessage {
+        ChatMessage {
+            content: self.edited_content.clone().unwrap_or_else(|| self.content.clone()),
+            edited_content: None,
+            ..self.clone()
+        }
+    }
+
+    /// The platform's stable id for the author, read from `additional_info`.
+    pub fn user_id(&self) -> Option<String> {
+        let info: serde_json::Value = serde_json::from_str(self.additional_info.as_deref()?).ok()?;
+        match &info["id"] {
+            serde_json::Value::String(id) => Some(id.clone()),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// Moderation state of a message, stored in `messages.status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    Pending,
+    Published,
+    Rejected,
+    Expired,
+}
+
+impl MessageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageStatus::Pending => "pending",
+            MessageStatus::Published => "published",
+            MessageStatus::Rejected => "rejected",
+            MessageStatus::Expired => "expired",
+        }
+    }
+
+    /// Whether a message with this status may be moved to `next`. Published
+    /// messages can only go back to pending through an unpublish.
+    pub fn can_become(self, next: MessageStatus) -> bool {
+        use MessageStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Published | Rejected | Expired)
+                | (Published, Pending | Rejected)
+                | (Rejected, Published)
+                | (Expired, Published | Rejected)
+        )
+    }
+}
+
+impl std::str::FromStr for MessageStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pending" => Ok(MessageStatus::Pending),
+            "published" => Ok(MessageStatus::Published),
+            "rejected" => Ok(MessageStatus::Rejected),
+            "expired" => Ok(MessageStatus::Expired),
+            _ => Err(anyhow::anyhow!("Unknown message status: {}", s)),
+        }
+    }
+}
+
+impl rusqlite::types::ToSql for MessageStatus {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl rusqlite::types::FromSql for MessageStatus {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: anyhow::Error| rusqlite::types::FromSqlError::Other(e.into()))
+    }
+}
+
+/// What an admin account may do. Each role can do everything the ones before it can.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Sees the queue, channels and settings but cannot change anything.
+    Viewer,
+    /// Publishes, rejects and unpublishes messages and manages the trusted and blocked users.
+    Moderator,
+    /// Manages channels, listeners, rules, filters and accounts.
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Moderator => "moderator",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl std::str::FromStr for Role {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "moderator" => Ok(Role::Moderator),
+            "owner" => Ok(Role::Owner),
+            _ => Err(anyhow::anyhow!("Unknown role: {}", s)),
+        }
+    }
+}
+
+impl rusqlite::types::ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl rusqlite::types::FromSql for Role {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: anyhow::Error| rusqlite::types::FromSqlError::Other(e.into()))
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct Account {
+    pub username: String,
+    #[serde(skip)]
+    pub password_hash: String,
+    pub role: Role,
+    pub created_at: u64,
+}
+
+/// Kinds of actions recorded in the audit log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Publish,
+    Reject,
+    Unpublish,
+    AddChannel,
+    DeleteChannel,
+    Listen,
+    Unlisten,
+    Edit,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Publish => "publish",
+            AuditAction::Reject => "reject",
+            AuditAction::Unpublish => "unpublish",
+            AuditAction::AddChannel => "add_channel",
+            AuditAction::DeleteChannel => "delete_channel",
+            AuditAction::Listen => "listen",
+            AuditAction::Unlisten => "unlisten",
+            AuditAction::Edit => "edit",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "publish" => Ok(AuditAction::Publish),
+            "reject" => Ok(AuditAction::Reject),
+            "unpublish" => Ok(AuditAction::Unpublish),
+            "add_channel" => Ok(AuditAction::AddChannel),
+            "delete_channel" => Ok(AuditAction::DeleteChannel),
+            "listen" => Ok(AuditAction::Listen),
+            "unlisten" => Ok(AuditAction::Unlisten),
+            "edit" => Ok(AuditAction::Edit),
+            _ => Err(anyhow::anyhow!("Unknown audit action: {}", s)),
+        }
+    }
+}
+
+impl rusqlite::types::ToSql for AuditAction {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        Ok(self.as_str().into())
+    }
+}
+
+impl rusqlite::types::FromSql for AuditAction {
+    fn column_result(value: rusqlite::types::ValueRef<'_>) -> rusqlite::types::FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|e: anyhow::Error| rusqlite::types::FromSqlError::Other(e.into()))
+    }
+}
+
+/// One row of the audit log. `before` and `after` hold the affected state,
+/// the message status or, for edits, the shown text. They are empty for
+/// channel actions.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct AuditEntry {
+    #[serde(default)]
+    pub id: Option<i64>,
+    pub timestamp: u64,
+    /// Username of the admin, or `rules` for automatic publishing.
+    pub actor: String,
+    pub action: AuditAction,
+    pub message_id: Option<MessageId>,
+    pub platform: String,
+    pub channel: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(actor: &str, action: AuditAction, platform: &str, channel: &str) -> Self {
+        AuditEntry {
+            id: None,
+            timestamp: chrono::Utc::now().timestamp_millis() as u64,
+            actor: actor.to_string(),
+            action,
+            message_id: None,
+            platform: platform.to_string(),
+            channel: channel.to_string(),
+            before: None,
+            after: None,
+        }
+    }
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct MessageConfirmation {
-    pub id: u128,
+    pub id: MessageId,
     pub allowed: bool,
 }
 
+/// Per-user lists that bypass manual moderation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserList {
+    /// Messages are published automatically.
+    Trusted,
+    /// Messages are rejected and never shown to moderators.
+    Blocked,
+}
+
+impl UserList {
+    pub fn table(&self) -> &'static str {
+        match self {
+            UserList::Trusted => "trusted_users",
+            UserList::Blocked => "blocked_users",
+        }
+    }
+}
+
+impl std::str::FromStr for UserList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "trusted" => Ok(UserList::Trusted),
+            "blocked" => Ok(UserList::Blocked),
+            _ => Err(anyhow::anyhow!("Unknown user list: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct UserListEntry {
+    pub platform: String,
+    pub user_id: String,
+    pub username: Option<String>,
+    pub added_at: u64,
+}
+
+/// In-memory copy of the trusted and blocked user tables, keyed by platform and user id.
+#[derive(Default)]
+pub struct UserLists {
+    pub trusted: HashSet<(String, String)>,
+    pub blocked: HashSet<(String, String)>,
+}
+
+impl UserLists {
+    pub fn get_mut(&mut self, list: UserList) -> &mut HashSet<(String, String)> {
+        match list {
+            UserList::Trusted => &mut self.trusted,
+            UserList::Blocked => &mut self.blocked,
+        }
+    }
+
+    /// Which list the author of a message is on, if any. Blocking wins over trust.
+    pub fn lookup(&self, chat_message: &ChatMessage) -> Option<UserList> {
+        let key = (chat_message.platform.clone(), chat_message.user_id()?);
+        if self.blocked.contains(&key) {
+            Some(UserList::Blocked)
+        } else if self.trusted.contains(&key) {
+            Some(UserList::Trusted)
+        } else {
+            None
+        }
+    }
+}
+
+/// What a filter checks a message for.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum FilterKind {
+    /// Case-insensitive whole-word matches.
+    BannedWords { words: Vec<String> },
+    Regex { pattern: String },
+    Links,
+    MaxLength { max_length: usize },
+}
+
+/// What happens to a message that matches a filter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterAction {
+    /// Discard the message without storing it.
+    Drop,
+    /// Store the message as rejected.
+    Reject,
+    /// Replace the matched text with asterisks. Over-long messages are truncated.
+    Mask,
+    /// Keep the message pending, but mark it for the moderator.
+    Flag,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct MessageFilter {
+    #[serde(default)]
+    pub id: Option<i64>,
+    #[serde(flatten)]
+    pub kind: FilterKind,
+    pub action: FilterAction,
+}
+
+/// How messages from a channel are published without a moderator.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum PublishMode {
+    /// Every message waits in the approval queue.
+    Manual,
+    All,
+    /// Publish messages from chatters with one of the given Twitch roles, or
+    /// subscribed for at least `min_sub_months`.
+    Roles {
+        #[serde(default)]
+        roles: Vec<String>,
+        #[serde(default)]
+        min_sub_months: Option<u16>,
+    },
+    /// Publish messages that are still pending after the delay.
+    Delay { delay_ms: u64 },
+}
+
+/// An auto-publish rule. `platform` and `channel` are `"*"` for the global rule.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PublishRule {
+    pub platform: String,
+    pub channel: String,
+    #[serde(flatten)]
+    pub mode: PublishMode,
+}
+
 pub struct AppState {
-    pub db_conn: Arc<Mutex<rusqlite::Connection>>,
-    pub admin_panel_sender: broadcast::Sender<ChatMessage>,
-    pub client_sender: broadcast::Sender<ChatMessage>,
+    pub config: Config,
+    /// Held while making a change that is broadcast until the events for it
+    /// are sent, and while taking the snapshots sockets catch up from. Events
+    /// then reach every socket in the order the changes were made, and a
+    /// snapshot never misses or repeats one. Never taken while already held.
+    pub event_order: Mutex<()>,
+    /// Everything that is persisted. Chat messages are written through
+    /// `message_writer`.
+    pub store: Box<dyn ChatStore>,
+    pub message_writer: MessageWriter,
+    pub admin_panel_sender: AdminBroadcaster,
+    pub client_sender: broadcast::Sender<ClientEvent>,
     pub active_connections: AtomicUsize,
-    pub listened_channels: Arc<Mutex<HashMap<String, HashMap<String, tokio::task::JoinHandle<()>>>>>, 
+    pub listened_channels: ListenerMap,
+    pub sources: SourceRegistry,
+    pub publish_rules: RwLock<Vec<PublishRule>>,
+    pub filters: RwLock<Vec<CompiledFilter>>,
+    pub user_lists: RwLock<UserLists>,
+    pub pinned_message: Mutex<Option<ChatMessage>>,
+    /// When the screen was last cleared; messages published before then are
+    /// not replayed to overlays, whenever they were received.
+    pub screen_cleared_at: AtomicU64,
+    pub sessions: Sessions,
+    /// Read-only token that lets an overlay connect to `/api/ws`.
+    pub overlay_token: RwLock<String>,
+}
+
+/// Running listeners, keyed by platform and then channel name.
+pub type ListenerMap = Arc<Mutex<HashMap<String, HashMap<String, Listener>>>>;
+
+pub struct Listener {
+    pub handle: tokio::task::JoinHandle<()>,
+    pub status: Arc<Mutex<ListenerStatus>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListenerState {
+    Connecting,
+    Live,
+    Reconnecting,
+    WaitingForStream,
+    Failed,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct ListenerStatus {
+    pub platform: String,
+    pub channel: String,
+    pub state: ListenerState,
+    pub message_count: u64,
+    pub last_message_at: Option<u64>,
+    pub last_error: Option<String>,
+    pub updated_at: u64,
 }
 
 #[derive(Parser, Debug, Clone)]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    /// TOML file with the server, database, listeners, filters and auth settings
+    #[arg(short, long)]
+    pub config: Option<String>,
+
     /// The address to bind the server to
-    #[arg(short = 'H', long, default_value = "127.0.0.1")]
-    pub host: String,
+    #[arg(short = 'H', long)]
+    pub host: Option<String>,
     
     /// The port to bind the server to
-    #[arg(short, long, default_value = "3000")]
-    pub port: u16,
+    #[arg(short, long)]
+    pub port: Option<u16>,
+
+    /// How many events each websocket may fall behind before it has to resync
+    #[arg(long)]
+    pub broadcast_capacity: Option<usize>,
+
+    /// Applies the configured admin password to the existing `admin` account
+    /// and makes it an owner again
+    #[arg(long)]
+    pub reset_admin: bool,
+
+    /// Origin allowed to call the API from another site; may be repeated
+    #[arg(long = "allowed-origin")]
+    pub allowed_origins: Vec<String>,
 }
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(Clone, serde::Serialize, serde::Deserialize, Debug)]
 pub struct Channel {
-    pub id: String,
+    pub id: ChannelId,
     pub name: String,
     pub platform: String,
     pub listen: bool,
+    pub last_error: Option<String>,
 }
\ No newline at end of file
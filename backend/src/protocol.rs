@@ -0,0 +1,136 @@
+//! Wire types for the `/api/ws` overlay socket and the `/api/admin/ws` admin socket.
+//!
+//! Every frame the server sends is a JSON object wrapped in an [`Envelope`]: a
+//! `version` field set to [`PROTOCOL_VERSION`] and a `type` field naming the
+//! event, with the event's own fields next to them:
+//!
+//! ```json
+//! {"version":1,"type":"message_published","id":"...","platform":"twitch",...}
+//! {"version":1,"type":"message_retracted","id":"..."}
+//! ```
+//!
+//! Overlays receive [`ClientEvent`]s. Admin sockets receive [`AdminEvent`]s,
+//! which also carry a `seq` field (see `events::SequencedEvent`) except for
+//! replies to commands. Admin sockets send [`AdminRequest`]s, tagged by
+//! `command` instead of `type`.
+//!
+//! The version is bumped whenever a field or event is removed or changes
+//! meaning. New events and fields may be added without a bump, so clients
+//! should ignore what they do not know.
+
+use crate::models::{ChatMessage, ListenerStatus, MessageId};
+
+/// Version of the frame format described in this module.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// A server frame: an event tagged with the protocol version.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct Envelope<T> {
+    pub version: u32,
+    #[serde(flatten)]
+    pub event: T,
+}
+
+/// Serializes an event as a versioned frame.
+pub fn frame<T: serde::Serialize>(event: T) -> serde_json::Result<String> {
+    serde_json::to_string(&Envelope { version: PROTOCOL_VERSION, event })
+}
+
+/// Events pushed to overlay clients on `/api/ws`.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
+pub enum ClientEvent {
+    MessagePublished(ChatMessage),
+    MessageRetracted { id: MessageId },
+    /// A published message was edited; replaces the shown message with the same id.
+    MessageEdited(ChatMessage),
+    MessagePinned(ChatMessage),
+    MessageUnpinned { id: MessageId },
+    /// Everything currently shown should be removed, including the pinned message.
+    ScreenCleared,
+    /// The overlay fell behind and `missed` events were dropped. The latest
+    /// published messages are sent again right after.
+    Lagged { missed: u64 },
+}
+
+/// Events pushed to the admin panel websocket.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AdminEvent {
+    /// A new message came in from a listened channel.
+    Message(ChatMessage),
+    /// A message changed status, e.g. after it was published or rejected.
+    MessageUpdated(ChatMessage),
+    ListenerStatus(ListenerStatus),
+    ListenerStopped { platform: String, channel: String },
+    /// Reply to a command that succeeded. Only sent to the socket that issued it.
+    Ack {
+        request_id: Option<String>,
+        command: String,
+        ids: Vec<MessageId>,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        failed: Vec<MessageId>,
+    },
+    /// Reply to a command that failed. Only sent to the socket that issued it.
+    Error {
+        request_id: Option<String>,
+        message: String,
+    },
+    /// Every pending message at sequence number `seq` of `stream`. Sent to a
+    /// newly connected admin before any other event; later events continue
+    /// from `seq`. Admins resume with both values.
+    Snapshot {
+        stream: String,
+        seq: u64,
+        messages: Vec<ChatMessage>,
+    },
+    /// The socket fell behind and `missed` events were dropped. Followed by the
+    /// buffered events or a new snapshot.
+    Lagged { missed: u64 },
+}
+
+/// A command sent by the admin panel over its websocket.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct AdminRequest {
+    /// Echoed back in the reply so the client can match it to the command.
+    #[serde(default)]
+    pub request_id: Option<String>,
+    #[serde(flatten)]
+    pub command: AdminCommand,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum AdminCommand {
+    Publish { id: MessageId },
+    Reject { id: MessageId },
+    Unpublish { id: MessageId },
+    /// Shows `content` instead of the original text. `None` restores the original.
+    Edit { id: MessageId, content: Option<String> },
+    Pin { id: MessageId },
+    Unpin,
+    /// Clears every overlay. Messages published before this are not replayed
+    /// to overlays that connect later.
+    ClearScreen,
+    Bulk { action: BulkAction, ids: Vec<MessageId> },
+    /// Only receive message events for these channels. An empty list subscribes to all channels.
+    Subscribe {
+        #[serde(default)]
+        channels: Vec<ChannelKey>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkAction {
+    Publish,
+    Reject,
+    Unpublish,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct ChannelKey {
+    pub platform: String,
+    pub channel: String,
+}
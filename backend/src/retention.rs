@@ -0,0 +1,82 @@
+//! Deletes old messages according to `[retention]` so the database does not
+//! grow forever, and compacts the file once in a while afterwards.
+
+use std::{sync::Arc, time::{Duration, Instant}};
+
+use tracing::{info, warn};
+
+use crate::{models::{AppState, MessageStatus}, protocol::{AdminEvent, ClientEvent}, utils};
+
+/// Enforces the retention limits once, then compacts the database if `vacuum`
+/// is set. Pending messages that waited too long expire first, so the same run
+/// can delete them. Overlays are told to remove deleted messages that were published,
+/// and to unpin the pinned one if it was deleted. Returns how many messages
+/// were deleted and whether it compacted.
+/// Blocks, so async callers run it through `spawn_blocking`.
+pub fn prune(state: &AppState, vacuum: bool) -> anyhow::Result<(usize, bool)> {
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    let deleted = {
+        // Held until the retractions are sent so overlays connecting meanwhile
+        // either get the deleted messages and their retractions, or neither.
+        let _order = state.event_order.lock().unwrap();
+        let expired = state.store.expire_pending(&state.config.retention, now)?;
+        if !expired.is_empty() {
+            info!("Retention expired {} pending messages", expired.len());
+        }
+        for message in expired {
+            state.admin_panel_sender.send(AdminEvent::MessageUpdated(message));
+        }
+
+        let deleted = state.store.prune(&state.config.retention, now)?;
+        for (id, status) in &deleted {
+            if *status == MessageStatus::Published {
+                let _ = state.client_sender.send(ClientEvent::MessageRetracted { id: *id });
+                utils::unpin_message(state, *id);
+            }
+        }
+        deleted.len()
+    };
+    if deleted > 0 {
+        info!("Retention deleted {} messages", deleted);
+    }
+
+    if vacuum {
+        state.store.vacuum()?;
+    }
+    Ok((deleted, vacuum))
+}
+
+/// Enforces the retention limits every `interval_minutes` for as long as the
+/// server runs. Compacts at most every `vacuum_interval_hours`, and only when
+/// messages were deleted since the last time.
+pub async fn run(state: Arc<AppState>) {
+    let retention = &state.config.retention;
+    if !retention.is_enabled() {
+        return;
+    }
+
+    let vacuum_interval = (retention.vacuum_interval_hours > 0)
+        .then(|| Duration::from_secs(retention.vacuum_interval_hours * 60 * 60));
+    let mut interval = tokio::time::interval(Duration::from_secs(retention.interval_minutes * 60));
+    let mut last_vacuum = Instant::now();
+    let mut deleted_since_vacuum = 0;
+
+    loop {
+        interval.tick().await;
+
+        let vacuum = deleted_since_vacuum > 0
+            && vacuum_interval.is_some_and(|vacuum_interval| last_vacuum.elapsed() >= vacuum_interval);
+        let task_state = state.clone();
+        match tokio::task::spawn_blocking(move || prune(&task_state, vacuum)).await {
+            Ok(Ok((deleted, vacuumed))) => {
+                deleted_since_vacuum += deleted;
+                if vacuumed {
+                    last_vacuum = Instant::now();
+                    deleted_since_vacuum = 0;
+                }
+            }
+            Ok(Err(e)) => warn!("Failed to enforce retention: {:?}", e),
+            Err(e) => warn!("Retention task panicked: {:?}", e),
+        }
+    }
+}
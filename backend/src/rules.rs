@@ -0,0 +1,75 @@
+use std::{sync::Arc, time::Duration};
+
+use tracing::{info, warn};
+
+use crate::{models::{AppState, ChatMessage, MessageStatus, PublishMode, PublishRule}, utils};
+
+/// Platform and channel key of the global rule.
+pub const GLOBAL: &str = "*";
+
+/// Finds the rule for a channel, falling back to the global rule.
+pub fn resolve(rules: &[PublishRule], platform: &str, channel: &str) -> PublishMode {
+    rules.iter()
+        .find(|rule| rule.platform == platform && rule.channel == channel)
+        .or_else(|| rules.iter().find(|rule| rule.platform == GLOBAL && rule.channel == GLOBAL))
+        .map(|rule| rule.mode.clone())
+        .unwrap_or(PublishMode::Manual)
+}
+
+/// Runs the auto-publish rules for a freshly stored message.
+pub fn apply(state: &Arc<AppState>, chat_message: &ChatMessage) {
+    let mode = resolve(&state.publish_rules.read().unwrap(), &chat_message.platform, &chat_message.channel);
+
+    match mode {
+        PublishMode::Manual => {}
+        PublishMode::All => publish(state, chat_message),
+        PublishMode::Roles { roles, min_sub_months } => {
+            if matches_roles(chat_message, &roles, min_sub_months) {
+                publish(state, chat_message);
+            }
+        }
+        PublishMode::Delay { delay_ms } => {
+            let state = state.clone();
+            let chat_message = chat_message.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+                publish(&state, &chat_message);
+            });
+        }
+    }
+}
+
+fn matches_roles(chat_message: &ChatMessage, roles: &[String], min_sub_months: Option<u16>) -> bool {
+    let Some(info) = chat_message.additional_info.as_deref()
+        .and_then(|info| serde_json::from_str::<serde_json::Value>(info).ok()) else {
+        return false;
+    };
+
+    let role_matches = info["role"].as_str()
+        .is_some_and(|role| roles.iter().any(|r| r.eq_ignore_ascii_case(role)));
+    let sub_matches = match (min_sub_months, info["sub_months"].as_u64()) {
+        (Some(min), Some(months)) => months >= min as u64,
+        _ => false,
+    };
+
+    role_matches || sub_matches
+}
+
+/// Actor recorded in the audit log for messages published by a rule or a trusted user.
+const AUTO_PUBLISH_ACTOR: &str = "rules";
+
+/// Publishes the message unless a moderator already handled it.
+pub fn publish(state: &AppState, chat_message: &ChatMessage) {
+    let _order = state.event_order.lock().unwrap();
+
+    match state.store.get_message(chat_message.id) {
+        Ok(Some(message)) if message.status == MessageStatus::Pending => {
+            info!("Auto-publishing message {}", chat_message.id);
+            if let Err(e) = utils::publish_message(state, chat_message.id, AUTO_PUBLISH_ACTOR) {
+                warn!("Failed to auto-publish message {}: {:?}", chat_message.id, e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Failed to load message {} for auto-publish: {:?}", chat_message.id, e),
+    }
+}
@@ -0,0 +1,191 @@
+use std::{collections::HashMap, sync::Arc};
+
+use brainrot::{twitch, youtube::{self, Action, ChatItem}, TwitchChat, TwitchChatEvent};
+use futures_util::{future::BoxFuture, stream::BoxStream, StreamExt};
+
+use crate::models::{ChatMessage, MessageId, MessageStatus};
+
+/// Stream of normalized chat messages produced by a connected source.
+pub type MessageStream = BoxStream<'static, anyhow::Result<ChatMessage>>;
+
+/// A chat platform that can be listened to.
+///
+/// Implementations only have to connect to a channel and turn platform events
+/// into `ChatMessage`s; persistence and broadcasting are handled by the shared
+/// listener pipeline.
+pub trait ChatSource: Send + Sync {
+    /// The platform key used in the API routes and the database.
+    fn platform(&self) -> &'static str;
+
+    fn connect(&self, channel: String) -> BoxFuture<'static, anyhow::Result<MessageStream>>;
+}
+
+/// Returned by `ChatSource::connect` when the channel exists but is not live yet.
+#[derive(Debug)]
+pub struct NoActiveStream(pub String);
+
+impl std::fmt::Display for NoActiveStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Channel {} has no active stream", self.0)
+    }
+}
+
+impl std::error::Error for NoActiveStream {}
+
+#[derive(Clone, Default)]
+pub struct SourceRegistry {
+    sources: HashMap<&'static str, Arc<dyn ChatSource>>,
+}
+
+impl SourceRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self::default();
+        registry.register(TwitchSource);
+        registry.register(YoutubeSource);
+        registry
+    }
+
+    pub fn register(&mut self, source: impl ChatSource + 'static) {
+        self.sources.insert(source.platform(), Arc::new(source));
+    }
+
+    pub fn get(&self, platform: &str) -> Option<Arc<dyn ChatSource>> {
+        self.sources.get(platform).cloned()
+    }
+}
+
+fn new_message(platform: &str, channel: &str, username: String, content: String, additional_info: Option<String>) -> ChatMessage {
+    ChatMessage {
+        id: MessageId::new(),
+        platform: platform.to_string(),
+        channel: channel.to_string(),
+        username,
+        content,
+        additional_info,
+        timestamp: chrono::Utc::now().timestamp_millis() as u64,
+        published: false,
+        status: MessageStatus::Pending,
+        flags: Vec::new(),
+        edited_content: None,
+        published_at: None,
+    }
+}
+
+pub struct TwitchSource;
+
+impl ChatSource for TwitchSource {
+    fn platform(&self) -> &'static str {
+        "twitch"
+    }
+
+    fn connect(&self, channel: String) -> BoxFuture<'static, anyhow::Result<MessageStream>> {
+        Box::pin(async move {
+            let client = TwitchChat::new(&channel, twitch::Anonymous).await?;
+
+            let stream = client.filter_map(move |event| {
+                let message = match event {
+                    Ok(TwitchChatEvent::Message { user, contents, .. }) => {
+                        let content = contents.iter().map(|c| c.to_string()).collect::<String>();
+                        let additional_info = serde_json::json!({
+                            "username": user.username,
+                            "id": user.id,
+                            "display_color": user.display_color,
+                            "sub_months": user.sub_months.map(|v| v.get()),
+                            "role": format!("{:?}", user.role),
+                            "returning_chatter": user.returning_chatter,
+                        }).to_string();
+
+                        Some(Ok(new_message("twitch", &channel, user.display_name, content, Some(additional_info))))
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some(Err(anyhow::anyhow!("Error receiving Twitch message: {:?}", e))),
+                };
+                futures_util::future::ready(message)
+            });
+
+            Ok(stream.boxed())
+        })
+    }
+}
+
+pub struct YoutubeSource;
+
+impl ChatSource for YoutubeSource {
+    fn platform(&self) -> &'static str {
+        "youtube"
+    }
+
+    fn connect(&self, channel: String) -> BoxFuture<'static, anyhow::Result<MessageStream>> {
+        Box::pin(async move {
+            let context = match youtube::ChatContext::new_from_channel(&channel, youtube::ChannelSearchOptions::LatestLiveOrUpcoming).await {
+                Ok(context) => context,
+                Err(youtube::Error::NoMatchingStream(_) | youtube::Error::NotStream(_)) => {
+                    return Err(NoActiveStream(channel).into());
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            // The brainrot stream borrows the context, so it is driven from its own
+            // task and forwarded through a channel to get an owned stream.
+            let (tx, mut rx) = tokio::sync::mpsc::channel::<anyhow::Result<ChatMessage>>(100);
+            let task = tokio::spawn(async move {
+                let mut stream = match youtube::stream(&context).await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        let _ = tx.send(Err(anyhow::anyhow!("Error creating YouTube stream: {:?}", e))).await;
+                        return;
+                    }
+                };
+
+                while let Some(action) = stream.next().await {
+                    let message = match action {
+                        Ok(Action::AddChatItem {
+                            item: ChatItem::TextMessage { message_renderer_base, message },
+                            ..
+                        }) => {
+                            let username = match &message_renderer_base.author_name {
+                                Some(name) => name.simple_text.clone(),
+                                None => "unknown".to_string()
+                            };
+                            let content = match &message {
+                                Some(msg) => msg.runs.iter().map(|run| run.to_chat_string()).collect::<String>(),
+                                None => "".to_string()
+                            };
+
+                            let additional_info = serde_json::json!({
+                                "id": message_renderer_base.author_external_channel_id,
+                            }).to_string();
+
+                            Ok(new_message("youtube", &channel, username, content, Some(additional_info)))
+                        }
+                        Ok(_) => continue,
+                        Err(e) => Err(anyhow::anyhow!("Error receiving YouTube message: {:?}", e)),
+                    };
+
+                    let failed = message.is_err();
+                    if tx.send(message).await.is_err() || failed {
+                        break;
+                    }
+                }
+            });
+
+            // Stopping the listener drops the stream, which stops the task too
+            // instead of leaving it connected until the next chat message.
+            let task = AbortOnDrop(task);
+            let stream = futures_util::stream::poll_fn(move |cx| {
+                let _task = &task;
+                rx.poll_recv(cx)
+            });
+            Ok(stream.boxed())
+        })
+    }
+}
+
+/// Aborts a task once its owner is dropped.
+struct AbortOnDrop(tokio::task::JoinHandle<()>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
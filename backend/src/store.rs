@@ -0,0 +1,585 @@
+//! Persistence of chat messages, channels, settings, the audit log, accounts,
+//! publish rules, filters and user lists.
+//!
+//! Handlers and listeners go through the `ChatStore` trait so the data can live
+//! in SQLite or, for ephemeral instances, only in memory.
+//!
+//! Implementations lock internally, so every method takes `&self`. Keeping the
+//! events for changes in order is up to callers; see `AppState::event_order`.
+
+use crate::{
+    config::{DatabaseConfig, RetentionConfig, StoreBackend},
+    db, migrations,
+    models::{
+        Account, AuditAction, AuditEntry, Channel, ChannelId, ChatMessage, MessageFilter, MessageId, MessageStatus, PublishRule,
+        Role, UserList, UserListEntry,
+    },
+};
+
+pub mod memory;
+pub mod sqlite;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+pub trait ChatStore: Send + Sync {
+    fn insert_message(&self, chat_message: &ChatMessage) -> anyhow::Result<()>;
+
+    /// Inserts several messages at once, in one transaction where the backend
+    /// has them. Returns whether each message was stored; a message that fails
+    /// is logged and skipped without affecting the others.
+    fn insert_messages(&self, chat_messages: &[ChatMessage]) -> anyhow::Result<Vec<bool>>;
+
+    fn get_message(&self, id: MessageId) -> anyhow::Result<Option<ChatMessage>>;
+
+    /// The latest messages, newest first, optionally only those older than `before`.
+    fn get_messages(&self, limit: usize, before: Option<u64>) -> anyhow::Result<Vec<ChatMessage>>;
+
+    /// Every message still waiting for a moderator, oldest first.
+    fn get_pending_messages(&self) -> anyhow::Result<Vec<ChatMessage>>;
+
+    /// The most recently published messages in the order they were published,
+    /// optionally only those published after `since`.
+    fn get_published_messages(&self, limit: usize, since: Option<u64>) -> anyhow::Result<Vec<ChatMessage>>;
+
+    /// Moves a message to a new status and records `action` by `actor` in the
+    /// audit log, both or neither. Taking a published message back to pending
+    /// also records who did it and when. Nothing is written if the message's
+    /// current status cannot become `status`.
+    fn set_message_status(&self, id: MessageId, status: MessageStatus, action: AuditAction, actor: &str) -> anyhow::Result<StatusChange>;
+
+    /// Sets or, with `None`, clears the text shown instead of the original, and
+    /// records the edit by `actor` in the audit log, both or neither.
+    /// Returns the updated message, or `None` if no message has that id.
+    fn set_edited_content(&self, id: MessageId, edited_content: Option<&str>, actor: &str) -> anyhow::Result<Option<ChatMessage>>;
+
+    /// Adds a channel, returning `None` if it already exists.
+    fn add_channel(&self, name: &str, platform: &str) -> anyhow::Result<Option<ChannelId>>;
+
+    fn get_channels(&self) -> anyhow::Result<Vec<Channel>>;
+
+    /// Stores whether a channel should be listened to, creating the channel if
+    /// it was never added.
+    fn set_channel_listen(&self, platform: &str, name: &str, listen: bool) -> anyhow::Result<()>;
+
+    fn set_channel_error(&self, platform: &str, name: &str, error: Option<&str>) -> anyhow::Result<()>;
+
+    fn delete_channel(&self, platform: &str, name: &str) -> anyhow::Result<()>;
+
+    fn get_setting(&self, key: &str) -> anyhow::Result<Option<String>>;
+
+    fn set_setting(&self, key: &str, value: &str) -> anyhow::Result<()>;
+
+    /// Records an action that has no other change to go with, returning the entry's id.
+    fn add_audit_entry(&self, entry: &AuditEntry) -> anyhow::Result<i64>;
+
+    fn get_audit_log(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>>;
+
+    /// Every account, oldest first.
+    fn get_accounts(&self) -> anyhow::Result<Vec<Account>>;
+
+    fn get_account(&self, username: &str) -> anyhow::Result<Option<Account>>;
+
+    /// Creates the account or replaces it.
+    fn save_account(&self, account: &Account) -> anyhow::Result<()>;
+
+    fn delete_account(&self, username: &str) -> anyhow::Result<()>;
+
+    /// How many accounts have `role`, or how many there are at all with `None`.
+    fn count_accounts(&self, role: Option<Role>) -> anyhow::Result<usize>;
+
+    fn get_publish_rules(&self) -> anyhow::Result<Vec<PublishRule>>;
+
+    /// Creates the rule for its platform and channel, or replaces the one there is.
+    fn save_publish_rule(&self, rule: &PublishRule) -> anyhow::Result<()>;
+
+    fn delete_publish_rule(&self, platform: &str, channel: &str) -> anyhow::Result<()>;
+
+    /// Filters added through the API, oldest first, with their ids set.
+    fn get_filters(&self) -> anyhow::Result<Vec<MessageFilter>>;
+
+    /// Stores a filter and returns its new id.
+    fn add_filter(&self, filter: &MessageFilter) -> anyhow::Result<i64>;
+
+    fn delete_filter(&self, id: i64) -> anyhow::Result<()>;
+
+    /// Users on `list`, most recently added first.
+    fn get_user_list(&self, list: UserList) -> anyhow::Result<Vec<UserListEntry>>;
+
+    /// Adds a user to `list`, replacing their entry if they are already on it.
+    fn add_user_to_list(&self, list: UserList, entry: &UserListEntry) -> anyhow::Result<()>;
+
+    fn remove_user_from_list(&self, list: UserList, platform: &str, user_id: &str) -> anyhow::Result<()>;
+
+    /// Marks the messages that waited in the pending queue longer than
+    /// `retention` allows as expired at time `now`, and returns them updated.
+    fn expire_pending(&self, retention: &RetentionConfig, now: u64) -> anyhow::Result<Vec<ChatMessage>>;
+
+    /// Deletes the messages `retention` no longer keeps at time `now` and
+    /// returns the id and status each deleted message had.
+    fn prune(&self, retention: &RetentionConfig, now: u64) -> anyhow::Result<Vec<(MessageId, MessageStatus)>>;
+
+    /// Gives the space of deleted messages back to the system.
+    fn vacuum(&self) -> anyhow::Result<()>;
+
+    /// Messages containing every search term, best match first. Later pages
+    /// only see the messages that were stored when the first page was read.
+    fn search_messages(&self, query: &SearchQuery) -> anyhow::Result<SearchPage>;
+}
+
+/// Outcome of `ChatStore::set_message_status`.
+#[derive(Debug)]
+pub enum StatusChange {
+    /// The message moved from status `from` and now looks like `message`.
+    Changed { from: MessageStatus, message: Box<ChatMessage> },
+    NotFound,
+    /// The message has this status, which cannot become the requested one.
+    NotAllowed(MessageStatus),
+}
+
+/// Which audit entries to return, newest first.
+#[derive(Debug, Default)]
+pub struct AuditQuery {
+    pub limit: usize,
+    /// Only entries with a smaller id, for paging backwards.
+    pub before: Option<i64>,
+    pub actor: Option<String>,
+    pub action: Option<AuditAction>,
+    pub message_id: Option<MessageId>,
+}
+
+impl AuditQuery {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.before.is_none_or(|before| entry.id.is_some_and(|id| id < before))
+            && self.actor.as_ref().is_none_or(|actor| &entry.actor == actor)
+            && self.action.is_none_or(|action| entry.action == action)
+            && self.message_id.is_none_or(|message_id| entry.message_id == Some(message_id))
+    }
+}
+
+/// The audit entry for `actor` moving a message from `before` to `after` status.
+fn status_audit_entry(before: &ChatMessage, after: MessageStatus, action: AuditAction, actor: &str) -> AuditEntry {
+    AuditEntry {
+        message_id: Some(before.id),
+        before: Some(before.status.as_str().to_string()),
+        after: Some(after.as_str().to_string()),
+        ..AuditEntry::new(actor, action, &before.platform, &before.channel)
+    }
+}
+
+/// The audit entry for `actor` changing the text overlays show from `before` to `after`.
+fn edit_audit_entry(before: &ChatMessage, after: &ChatMessage, actor: &str) -> AuditEntry {
+    AuditEntry {
+        message_id: Some(before.id),
+        before: Some(before.for_overlay().content),
+        after: Some(after.for_overlay().content),
+        ..AuditEntry::new(actor, AuditAction::Edit, &before.platform, &before.channel)
+    }
+}
+
+/// Which messages to search and where to continue from.
+#[derive(Debug, Default)]
+pub struct SearchQuery {
+    /// Words that must all start a word of the content or username; see `search_terms`.
+    pub terms: Vec<String>,
+    pub platform: Option<String>,
+    pub channel: Option<String>,
+    /// Author, compared without regard to case.
+    pub username: Option<String>,
+    pub status: Option<MessageStatus>,
+    pub limit: usize,
+    /// Only results ranked after this one, for the next page.
+    pub after: Option<SearchCursor>,
+}
+
+/// Where a page of search results ended. Results are ordered by score, lower
+/// being better, then newest first.
+///
+/// New messages would otherwise land on pages already read, so `snapshot`
+/// limits later pages to the messages stored when the first page was read: a
+/// backend's insertion position, like SQLite's rowid. Where scores depend on
+/// the other messages, backends page from the current score of message `id`
+/// and fall back to `score` once it is gone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SearchCursor {
+    pub snapshot: u64,
+    pub score: f64,
+    pub id: MessageId,
+}
+
+impl std::fmt::Display for SearchCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.snapshot, self.score, self.id)
+    }
+}
+
+impl std::str::FromStr for SearchCursor {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("Invalid search cursor: {}", s);
+        let mut parts = s.splitn(3, ':');
+        let (Some(snapshot), Some(score), Some(id)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(invalid());
+        };
+        Ok(SearchCursor {
+            snapshot: snapshot.parse().map_err(|_| invalid())?,
+            score: score.parse().map_err(|_| invalid())?,
+            id: id.parse()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize)]
+pub struct SearchResult {
+    #[serde(flatten)]
+    pub message: ChatMessage,
+    /// The matching part of the message as HTML, with the matched words in `<mark>`.
+    pub snippet: String,
+    #[serde(skip)]
+    pub score: f64,
+}
+
+/// One page of search results.
+#[derive(Debug)]
+pub struct SearchPage {
+    pub results: Vec<SearchResult>,
+    /// Where the next page starts; `None` on the last page.
+    pub next_cursor: Option<SearchCursor>,
+}
+
+impl SearchPage {
+    /// A page of `results` read at `snapshot`, full when it holds `limit` results.
+    fn new(results: Vec<SearchResult>, limit: usize, snapshot: u64) -> Self {
+        let next_cursor = results.last()
+            .filter(|_| results.len() == limit)
+            .map(|last| SearchCursor { snapshot, score: last.score, id: last.message.id });
+        SearchPage { results, next_cursor }
+    }
+}
+
+/// Splits a search into lowercase words the way the SQLite index splits text,
+/// dropping punctuation, so any input is a valid search.
+pub fn search_terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Marks the start and end of a matched word in the snippets backends build.
+const MATCH_START: char = '\u{2}';
+const MATCH_END: char = '\u{3}';
+
+/// Turns a snippet marked with `MATCH_START` and `MATCH_END` into escaped HTML.
+fn snippet_html(marked: &str) -> String {
+    let mut html = String::with_capacity(marked.len());
+    for c in marked.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+/// Milliseconds in an hour, for the retention ages.
+const HOUR_MS: u64 = 60 * 60 * 1000;
+
+/// Whether `retention` may delete a message with this status at all.
+fn is_prunable(retention: &RetentionConfig, status: MessageStatus) -> bool {
+    match status {
+        MessageStatus::Pending => false,
+        MessageStatus::Published => !retention.keep_published,
+        MessageStatus::Rejected | MessageStatus::Expired => true,
+    }
+}
+
+/// Opens the store the configuration asks for, migrating the SQLite database
+/// first.
+pub fn open(config: &DatabaseConfig) -> anyhow::Result<Box<dyn ChatStore>> {
+    Ok(match config.backend {
+        StoreBackend::Sqlite => {
+            migrations::run(&mut db::open(&config.path)?)?;
+            Box::new(SqliteStore::open(&config.path, config.read_connections)?)
+        }
+        StoreBackend::Memory => Box::new(MemoryStore::default()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A migrated SQLite database in the temp directory, deleted on drop.
+    struct TempDb(String);
+
+    impl TempDb {
+        fn new() -> Self {
+            let path = std::env::temp_dir().join(format!("stream-chat-reader-{}.db", uuid::Uuid::now_v7()));
+            let path = path.to_str().unwrap().to_string();
+            migrations::run(&mut db::open(&path).unwrap()).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.0, suffix));
+            }
+        }
+    }
+
+    /// Runs `check` against a fresh store of every backend, so both are held
+    /// to the same behaviour.
+    fn for_each_store(check: impl Fn(&dyn ChatStore)) {
+        let db = TempDb::new();
+        check(&SqliteStore::open(&db.0, 2).unwrap());
+        check(&MemoryStore::default());
+    }
+
+    fn message(content: &str, timestamp: u64) -> ChatMessage {
+        ChatMessage {
+            id: MessageId::new(),
+            platform: "twitch".to_string(),
+            channel: "a".to_string(),
+            username: "alice".to_string(),
+            content: content.to_string(),
+            additional_info: None,
+            timestamp,
+            published: false,
+            status: MessageStatus::Pending,
+            flags: Vec::new(),
+            edited_content: None,
+            published_at: None,
+        }
+    }
+
+    fn ids(messages: &[ChatMessage]) -> Vec<MessageId> {
+        messages.iter().map(|message| message.id).collect()
+    }
+
+    fn set_status(store: &dyn ChatStore, id: MessageId, status: MessageStatus, action: AuditAction) -> StatusChange {
+        let change = store.set_message_status(id, status, action, "mod").unwrap();
+        // Publish times are in milliseconds; keep consecutive changes apart.
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        change
+    }
+
+    #[test]
+    fn publishes_and_unpublishes_messages() {
+        for_each_store(|store| {
+            let [first, second, third] = [message("one", 1), message("two", 2), message("three", 3)];
+            for message in [&first, &second, &third] {
+                store.insert_message(message).unwrap();
+            }
+
+            let StatusChange::Changed { from, message } = set_status(store, third.id, MessageStatus::Published, AuditAction::Publish) else {
+                panic!("publishing a pending message failed");
+            };
+            assert_eq!(from, MessageStatus::Pending);
+            assert!(message.published);
+            assert!(message.published_at.is_some());
+            set_status(store, first.id, MessageStatus::Published, AuditAction::Publish);
+
+            assert_eq!(ids(&store.get_pending_messages().unwrap()), vec![second.id]);
+            // The backlog follows the publish order, not the receive order.
+            let published = store.get_published_messages(10, None).unwrap();
+            assert_eq!(ids(&published), vec![third.id, first.id]);
+            assert_eq!(ids(&store.get_published_messages(1, None).unwrap()), vec![first.id]);
+            assert_eq!(ids(&store.get_published_messages(10, published[0].published_at).unwrap()), vec![first.id]);
+
+            assert!(matches!(
+                set_status(store, third.id, MessageStatus::Pending, AuditAction::Unpublish),
+                StatusChange::Changed { from: MessageStatus::Published, .. }
+            ));
+            assert_eq!(ids(&store.get_published_messages(10, None).unwrap()), vec![first.id]);
+            assert_eq!(ids(&store.get_pending_messages().unwrap()), vec![second.id, third.id]);
+
+            assert!(matches!(
+                set_status(store, second.id, MessageStatus::Pending, AuditAction::Unpublish),
+                StatusChange::NotAllowed(MessageStatus::Pending)
+            ));
+            assert!(matches!(
+                set_status(store, first.id, MessageStatus::Published, AuditAction::Publish),
+                StatusChange::NotAllowed(MessageStatus::Published)
+            ));
+            assert!(matches!(
+                set_status(store, MessageId::new(), MessageStatus::Published, AuditAction::Publish),
+                StatusChange::NotFound
+            ));
+
+            // Only the changes that happened are in the audit log, newest first.
+            let audit = store.get_audit_log(&AuditQuery { limit: 10, ..Default::default() }).unwrap();
+            let actions: Vec<_> = audit.iter().map(|entry| (entry.action, entry.message_id)).collect();
+            assert_eq!(actions, vec![
+                (AuditAction::Unpublish, Some(third.id)),
+                (AuditAction::Publish, Some(first.id)),
+                (AuditAction::Publish, Some(third.id)),
+            ]);
+        });
+    }
+
+    #[test]
+    fn edits_keep_the_original_content() {
+        for_each_store(|store| {
+            let original = message("original", 1);
+            store.insert_message(&original).unwrap();
+
+            let edited = store.set_edited_content(original.id, Some("edited"), "mod").unwrap().unwrap();
+            assert_eq!(edited.content, "original");
+            assert_eq!(edited.edited_content.as_deref(), Some("edited"));
+            assert_eq!(edited.for_overlay().content, "edited");
+
+            let restored = store.set_edited_content(original.id, None, "mod").unwrap().unwrap();
+            assert_eq!(restored.edited_content, None);
+            assert!(store.set_edited_content(MessageId::new(), Some("edited"), "mod").unwrap().is_none());
+        });
+    }
+
+    #[test]
+    fn settings_round_trip() {
+        for_each_store(|store| {
+            assert_eq!(store.get_setting("overlay_token").unwrap(), None);
+            store.set_setting("overlay_token", "first").unwrap();
+            assert_eq!(store.get_setting("overlay_token").unwrap().as_deref(), Some("first"));
+            store.set_setting("overlay_token", "second").unwrap();
+            assert_eq!(store.get_setting("overlay_token").unwrap().as_deref(), Some("second"));
+        });
+    }
+
+    #[test]
+    fn accounts_round_trip() {
+        for_each_store(|store| {
+            let account = |username: &str, role, created_at| Account {
+                username: username.to_string(),
+                password_hash: "hash".to_string(),
+                role,
+                created_at,
+            };
+            store.save_account(&account("bob", Role::Moderator, 2)).unwrap();
+            store.save_account(&account("admin", Role::Owner, 1)).unwrap();
+            store.save_account(&account("bob", Role::Viewer, 2)).unwrap();
+
+            let usernames: Vec<_> = store.get_accounts().unwrap().into_iter().map(|account| account.username).collect();
+            assert_eq!(usernames, vec!["admin", "bob"]);
+            assert_eq!(store.get_account("bob").unwrap().map(|account| account.role), Some(Role::Viewer));
+            assert_eq!(store.count_accounts(None).unwrap(), 2);
+            assert_eq!(store.count_accounts(Some(Role::Owner)).unwrap(), 1);
+            assert_eq!(store.count_accounts(Some(Role::Moderator)).unwrap(), 0);
+
+            store.delete_account("bob").unwrap();
+            assert!(store.get_account("bob").unwrap().is_none());
+            assert_eq!(store.count_accounts(None).unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn rules_filters_and_user_lists_round_trip() {
+        use crate::models::{FilterAction, FilterKind, PublishMode};
+
+        for_each_store(|store| {
+            let rule = |channel: &str, mode| PublishRule { platform: "twitch".to_string(), channel: channel.to_string(), mode };
+            store.save_publish_rule(&rule("a", PublishMode::All)).unwrap();
+            store.save_publish_rule(&rule("b", PublishMode::All)).unwrap();
+            store.save_publish_rule(&rule("a", PublishMode::Delay { delay_ms: 5 })).unwrap();
+            store.delete_publish_rule("twitch", "b").unwrap();
+            let rules = store.get_publish_rules().unwrap();
+            assert_eq!(rules.len(), 1);
+            assert_eq!(rules[0].mode, PublishMode::Delay { delay_ms: 5 });
+
+            let filter = |action| MessageFilter { id: None, kind: FilterKind::Links, action };
+            let flag = store.add_filter(&filter(FilterAction::Flag)).unwrap();
+            let reject = store.add_filter(&filter(FilterAction::Reject)).unwrap();
+            store.delete_filter(reject).unwrap();
+            assert!(store.add_filter(&filter(FilterAction::Mask)).unwrap() > reject);
+            let filters: Vec<_> = store.get_filters().unwrap().into_iter().map(|filter| filter.id.unwrap()).collect();
+            assert_eq!(filters, vec![flag, reject + 1]);
+
+            let entry = |user_id: &str, username: &str, added_at| UserListEntry {
+                platform: "twitch".to_string(),
+                user_id: user_id.to_string(),
+                username: Some(username.to_string()),
+                added_at,
+            };
+            store.add_user_to_list(UserList::Trusted, &entry("1", "alice", 1)).unwrap();
+            store.add_user_to_list(UserList::Trusted, &entry("2", "bob", 2)).unwrap();
+            store.add_user_to_list(UserList::Trusted, &entry("1", "alice2", 3)).unwrap();
+            store.add_user_to_list(UserList::Blocked, &entry("3", "carol", 4)).unwrap();
+            store.remove_user_from_list(UserList::Blocked, "twitch", "3").unwrap();
+            let trusted: Vec<_> = store.get_user_list(UserList::Trusted).unwrap().into_iter().filter_map(|entry| entry.username).collect();
+            assert_eq!(trusted, vec!["alice2", "bob"]);
+            assert!(store.get_user_list(UserList::Blocked).unwrap().is_empty());
+        });
+    }
+
+    #[test]
+    fn search_pages_leave_out_later_messages() {
+        for_each_store(|store| {
+            let stored = [message("hello one", 1), message("hello two", 2), message("hello three", 3)];
+            for message in &stored {
+                store.insert_message(message).unwrap();
+            }
+
+            let mut query = SearchQuery { terms: search_terms("hello"), limit: 2, ..Default::default() };
+            let first = store.search_messages(&query).unwrap();
+            assert_eq!(first.results.len(), 2);
+            store.insert_message(&message("hello hello four", 4)).unwrap();
+
+            query.after = first.next_cursor;
+            let second = store.search_messages(&query).unwrap();
+            assert!(second.next_cursor.is_none());
+            let mut found: Vec<_> = first.results.iter().chain(&second.results).map(|result| result.message.id).collect();
+            found.sort();
+            let mut expected = ids(&stored);
+            expected.sort();
+            assert_eq!(found, expected);
+
+            let cursor = query.after.unwrap();
+            assert_eq!(cursor.to_string().parse::<SearchCursor>().unwrap(), cursor);
+        });
+    }
+
+    #[test]
+    fn prunes_the_same_messages() {
+        for_each_store(|store| {
+            let now = 10 * HOUR_MS;
+            let [old_pending, old_published, old_rejected, recent_rejected, recent_pending] =
+                [message("a", HOUR_MS), message("b", HOUR_MS), message("c", HOUR_MS), message("d", now - 1), message("e", now - 1)];
+            for message in [&old_pending, &old_published, &old_rejected, &recent_rejected, &recent_pending] {
+                store.insert_message(message).unwrap();
+            }
+            set_status(store, old_published.id, MessageStatus::Published, AuditAction::Publish);
+            set_status(store, old_rejected.id, MessageStatus::Rejected, AuditAction::Reject);
+            set_status(store, recent_rejected.id, MessageStatus::Rejected, AuditAction::Reject);
+
+            let retention = RetentionConfig { max_age_hours: Some(2), keep_published: false, ..Default::default() };
+            assert!(store.expire_pending(&retention, now).unwrap().is_empty());
+            let mut deleted = store.prune(&retention, now).unwrap();
+            deleted.sort_by_key(|(id, _)| *id);
+            let mut expected = vec![(old_published.id, MessageStatus::Published), (old_rejected.id, MessageStatus::Rejected)];
+            expected.sort_by_key(|(id, _)| *id);
+            assert_eq!(deleted, expected);
+
+            let mut kept = ids(&store.get_messages(10, None).unwrap());
+            kept.sort();
+            let mut expected = vec![old_pending.id, recent_rejected.id, recent_pending.id];
+            expected.sort();
+            assert_eq!(kept, expected);
+
+            let retention = RetentionConfig { pending_max_age_hours: Some(2), ..retention };
+            let expired = store.expire_pending(&retention, now).unwrap();
+            assert_eq!(ids(&expired), vec![old_pending.id]);
+            assert_eq!(expired[0].status, MessageStatus::Expired);
+            assert_eq!(store.prune(&retention, now).unwrap(), vec![(old_pending.id, MessageStatus::Expired)]);
+            assert_eq!(store.get_message(recent_pending.id).unwrap().unwrap().status, MessageStatus::Pending);
+        });
+    }
+}
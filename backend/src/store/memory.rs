@@ -0,0 +1,440 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use crate::{
+    config::RetentionConfig,
+    models::{
+        Account, AuditAction, AuditEntry, Channel, ChannelId, ChatMessage, MessageFilter, MessageId, MessageStatus, PublishRule,
+        Role, UserList, UserListEntry,
+    },
+};
+
+use super::{
+    edit_audit_entry, is_prunable, snippet_html, status_audit_entry, AuditQuery, ChatStore, SearchPage, SearchQuery, SearchResult,
+    StatusChange, HOUR_MS, MATCH_END, MATCH_START,
+};
+
+/// Keeps everything in memory, so it is gone when the server stops. Meant for
+/// ephemeral instances and tests; queries scan every message.
+#[derive(Default)]
+pub struct MemoryStore {
+    data: RwLock<Data>,
+}
+
+#[derive(Default)]
+struct Data {
+    messages: HashMap<MessageId, ChatMessage>,
+    /// When each message was inserted, counting from one like SQLite's rowid,
+    /// so searches can leave out messages that arrived after their first page.
+    positions: HashMap<MessageId, u64>,
+    last_position: u64,
+    /// Who took a message back and when, by message id.
+    unpublished: HashMap<MessageId, (String, u64)>,
+    channels: Vec<Channel>,
+    settings: HashMap<String, String>,
+    /// Oldest first; an entry's id is its position plus one.
+    audit_log: Vec<AuditEntry>,
+    accounts: HashMap<String, Account>,
+    publish_rules: Vec<PublishRule>,
+    /// Oldest first, with their ids set.
+    filters: Vec<MessageFilter>,
+    /// Never reused, like SQLite's AUTOINCREMENT.
+    last_filter_id: i64,
+    user_lists: HashMap<UserList, Vec<UserListEntry>>,
+}
+
+impl Data {
+    /// Messages matching `filter`, sorted by timestamp.
+    fn messages_by_time(&self, filter: impl Fn(&ChatMessage) -> bool) -> Vec<ChatMessage> {
+        let mut messages: Vec<ChatMessage> = self.messages.values().filter(|message| filter(message)).cloned().collect();
+        messages.sort_by_key(|message| (message.timestamp, message.id));
+        messages
+    }
+
+    fn add_channel(&mut self, name: &str, platform: &str) -> Option<ChannelId> {
+        if self.channels.iter().any(|channel| channel.name == name && channel.platform == platform) {
+            return None;
+        }
+        let id = ChannelId::new();
+        self.channels.push(Channel {
+            id,
+            name: name.to_string(),
+            platform: platform.to_string(),
+            listen: false,
+            last_error: None,
+        });
+        Some(id)
+    }
+
+    fn add_audit_entry(&mut self, entry: &AuditEntry) -> i64 {
+        let id = self.audit_log.len() as i64 + 1;
+        self.audit_log.push(AuditEntry { id: Some(id), ..entry.clone() });
+        id
+    }
+
+    fn channel_mut(&mut self, platform: &str, name: &str) -> Option<&mut Channel> {
+        self.channels.iter_mut().find(|channel| channel.name == name && channel.platform == platform)
+    }
+}
+
+impl ChatStore for MemoryStore {
+    fn insert_message(&self, chat_message: &ChatMessage) -> anyhow::Result<()> {
+        let mut data = self.data.write().unwrap();
+        if data.messages.contains_key(&chat_message.id) {
+            return Err(anyhow::anyhow!("Message {} already exists", chat_message.id));
+        }
+        data.messages.insert(chat_message.id, chat_message.clone());
+        data.last_position += 1;
+        let position = data.last_position;
+        data.positions.insert(chat_message.id, position);
+        Ok(())
+    }
+
+    fn insert_messages(&self, chat_messages: &[ChatMessage]) -> anyhow::Result<Vec<bool>> {
+        Ok(chat_messages.iter()
+            .map(|chat_message| self.insert_message(chat_message).is_ok())
+            .collect())
+    }
+
+    fn get_message(&self, id: MessageId) -> anyhow::Result<Option<ChatMessage>> {
+        Ok(self.data.read().unwrap().messages.get(&id).cloned())
+    }
+
+    fn get_messages(&self, limit: usize, before: Option<u64>) -> anyhow::Result<Vec<ChatMessage>> {
+        let mut messages = self.data.read().unwrap()
+            .messages_by_time(|message| before.is_none_or(|before| message.timestamp < before));
+        messages.reverse();
+        messages.truncate(limit);
+        Ok(messages)
+    }
+
+    fn get_pending_messages(&self) -> anyhow::Result<Vec<ChatMessage>> {
+        Ok(self.data.read().unwrap().messages_by_time(|message| message.status == MessageStatus::Pending))
+    }
+
+    fn get_published_messages(&self, limit: usize, since: Option<u64>) -> anyhow::Result<Vec<ChatMessage>> {
+        let since = since.unwrap_or(0);
+        let mut messages: Vec<ChatMessage> = self.data.read().unwrap().messages.values()
+            .filter(|message| message.status == MessageStatus::Published && message.published_at.is_some_and(|published_at| published_at > since))
+            .cloned()
+            .collect();
+        messages.sort_by_key(|message| (message.published_at, message.id));
+        messages.drain(..messages.len().saturating_sub(limit));
+        Ok(messages)
+    }
+
+    fn set_message_status(&self, id: MessageId, status: MessageStatus, action: AuditAction, actor: &str) -> anyhow::Result<StatusChange> {
+        let mut data = self.data.write().unwrap();
+        let Some(message) = data.messages.get_mut(&id) else {
+            return Ok(StatusChange::NotFound);
+        };
+        let from = message.status;
+        if !from.can_become(status) {
+            return Ok(StatusChange::NotAllowed(from));
+        }
+
+        let entry = status_audit_entry(message, status, action, actor);
+        message.status = status;
+        message.published = status == MessageStatus::Published;
+        if status == MessageStatus::Published {
+            message.published_at = Some(entry.timestamp);
+        }
+        let after = message.clone();
+
+        if from == MessageStatus::Published && status == MessageStatus::Pending {
+            data.unpublished.insert(id, (actor.to_string(), entry.timestamp));
+        }
+        data.add_audit_entry(&entry);
+        Ok(StatusChange::Changed { from, message: Box::new(after) })
+    }
+
+    fn set_edited_content(&self, id: MessageId, edited_content: Option<&str>, actor: &str) -> anyhow::Result<Option<ChatMessage>> {
+        let mut data = self.data.write().unwrap();
+        let Some(message) = data.messages.get_mut(&id) else {
+            return Ok(None);
+        };
+
+        let before = message.clone();
+        message.edited_content = edited_content.map(str::to_string);
+        let after = message.clone();
+        data.add_audit_entry(&edit_audit_entry(&before, &after, actor));
+        Ok(Some(after))
+    }
+
+    fn add_channel(&self, name: &str, platform: &str) -> anyhow::Result<Option<ChannelId>> {
+        Ok(self.data.write().unwrap().add_channel(name, platform))
+    }
+
+    fn get_channels(&self) -> anyhow::Result<Vec<Channel>> {
+        Ok(self.data.read().unwrap().channels.clone())
+    }
+
+    fn set_channel_listen(&self, platform: &str, name: &str, listen: bool) -> anyhow::Result<()> {
+        let mut data = self.data.write().unwrap();
+        if data.channel_mut(platform, name).is_none() && listen {
+            data.add_channel(name, platform);
+        }
+        if let Some(channel) = data.channel_mut(platform, name) {
+            channel.listen = listen;
+        }
+        Ok(())
+    }
+
+    fn set_channel_error(&self, platform: &str, name: &str, error: Option<&str>) -> anyhow::Result<()> {
+        if let Some(channel) = self.data.write().unwrap().channel_mut(platform, name) {
+            channel.last_error = error.map(str::to_string);
+        }
+        Ok(())
+    }
+
+    fn delete_channel(&self, platform: &str, name: &str) -> anyhow::Result<()> {
+        self.data.write().unwrap().channels.retain(|channel| !(channel.name == name && channel.platform == platform));
+        Ok(())
+    }
+
+    fn get_setting(&self, key: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.data.read().unwrap().settings.get(key).cloned())
+    }
+
+    fn set_setting(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.data.write().unwrap().settings.insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    fn add_audit_entry(&self, entry: &AuditEntry) -> anyhow::Result<i64> {
+        Ok(self.data.write().unwrap().add_audit_entry(entry))
+    }
+
+    fn get_audit_log(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        Ok(self.data.read().unwrap().audit_log.iter()
+            .rev()
+            .filter(|entry| query.matches(entry))
+            .take(query.limit)
+            .cloned()
+            .collect())
+    }
+
+    fn get_accounts(&self) -> anyhow::Result<Vec<Account>> {
+        let mut accounts: Vec<Account> = self.data.read().unwrap().accounts.values().cloned().collect();
+        accounts.sort_by_key(|account| account.created_at);
+        Ok(accounts)
+    }
+
+    fn get_account(&self, username: &str) -> anyhow::Result<Option<Account>> {
+        Ok(self.data.read().unwrap().accounts.get(username).cloned())
+    }
+
+    fn save_account(&self, account: &Account) -> anyhow::Result<()> {
+        self.data.write().unwrap().accounts.insert(account.username.clone(), account.clone());
+        Ok(())
+    }
+
+    fn delete_account(&self, username: &str) -> anyhow::Result<()> {
+        self.data.write().unwrap().accounts.remove(username);
+        Ok(())
+    }
+
+    fn count_accounts(&self, role: Option<Role>) -> anyhow::Result<usize> {
+        Ok(self.data.read().unwrap().accounts.values()
+            .filter(|account| role.is_none_or(|role| account.role == role))
+            .count())
+    }
+
+    fn get_publish_rules(&self) -> anyhow::Result<Vec<PublishRule>> {
+        Ok(self.data.read().unwrap().publish_rules.clone())
+    }
+
+    fn save_publish_rule(&self, rule: &PublishRule) -> anyhow::Result<()> {
+        let rules = &mut self.data.write().unwrap().publish_rules;
+        match rules.iter_mut().find(|saved| saved.platform == rule.platform && saved.channel == rule.channel) {
+            Some(saved) => *saved = rule.clone(),
+            None => rules.push(rule.clone()),
+        }
+        Ok(())
+    }
+
+    fn delete_publish_rule(&self, platform: &str, channel: &str) -> anyhow::Result<()> {
+        self.data.write().unwrap().publish_rules.retain(|rule| !(rule.platform == platform && rule.channel == channel));
+        Ok(())
+    }
+
+    fn get_filters(&self) -> anyhow::Result<Vec<MessageFilter>> {
+        Ok(self.data.read().unwrap().filters.clone())
+    }
+
+    fn add_filter(&self, filter: &MessageFilter) -> anyhow::Result<i64> {
+        let mut data = self.data.write().unwrap();
+        data.last_filter_id += 1;
+        let id = data.last_filter_id;
+        data.filters.push(MessageFilter { id: Some(id), ..filter.clone() });
+        Ok(id)
+    }
+
+    fn delete_filter(&self, id: i64) -> anyhow::Result<()> {
+        self.data.write().unwrap().filters.retain(|filter| filter.id != Some(id));
+        Ok(())
+    }
+
+    fn get_user_list(&self, list: UserList) -> anyhow::Result<Vec<UserListEntry>> {
+        let mut entries = self.data.read().unwrap().user_lists.get(&list).cloned().unwrap_or_default();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.added_at));
+        Ok(entries)
+    }
+
+    fn add_user_to_list(&self, list: UserList, entry: &UserListEntry) -> anyhow::Result<()> {
+        let mut data = self.data.write().unwrap();
+        let entries = data.user_lists.entry(list).or_default();
+        entries.retain(|saved| !(saved.platform == entry.platform && saved.user_id == entry.user_id));
+        entries.push(entry.clone());
+        Ok(())
+    }
+
+    fn remove_user_from_list(&self, list: UserList, platform: &str, user_id: &str) -> anyhow::Result<()> {
+        if let Some(entries) = self.data.write().unwrap().user_lists.get_mut(&list) {
+            entries.retain(|entry| !(entry.platform == platform && entry.user_id == user_id));
+        }
+        Ok(())
+    }
+
+    fn expire_pending(&self, retention: &RetentionConfig, now: u64) -> anyhow::Result<Vec<ChatMessage>> {
+        let Some(hours) = retention.pending_max_age_hours else {
+            return Ok(Vec::new());
+        };
+        let cutoff = now.saturating_sub(hours * HOUR_MS);
+
+        let mut data = self.data.write().unwrap();
+        Ok(data.messages.values_mut()
+            .filter(|message| message.status == MessageStatus::Pending && message.timestamp < cutoff)
+            .map(|message| {
+                message.status = MessageStatus::Expired;
+                message.clone()
+            })
+            .collect())
+    }
+
+    fn prune(&self, retention: &RetentionConfig, now: u64) -> anyhow::Result<Vec<(MessageId, MessageStatus)>> {
+        let mut data = self.data.write().unwrap();
+        let mut deleted = Vec::new();
+
+        let cutoff = |hours: Option<u64>| hours.map(|hours| now.saturating_sub(hours * HOUR_MS));
+        let max_age = cutoff(retention.max_age_hours);
+        let rejected_max_age = cutoff(retention.rejected_max_age_hours);
+        data.messages.retain(|id, message| {
+            let too_old = max_age.is_some_and(|cutoff| message.timestamp < cutoff);
+            let rejected_too_old = message.status == MessageStatus::Rejected
+                && rejected_max_age.is_some_and(|cutoff| message.timestamp < cutoff);
+            let prune = is_prunable(retention, message.status) && too_old || rejected_too_old;
+            if prune {
+                deleted.push((*id, message.status));
+            }
+            !prune
+        });
+
+        if let Some(max) = retention.max_messages_per_channel {
+            let mut by_channel: HashMap<(String, String), Vec<(u64, MessageId)>> = HashMap::new();
+            for message in data.messages.values() {
+                by_channel.entry((message.platform.clone(), message.channel.clone()))
+                    .or_default()
+                    .push((message.timestamp, message.id));
+            }
+            for mut messages in by_channel.into_values() {
+                messages.sort_unstable_by(|a, b| b.cmp(a));
+                for (_, id) in messages.into_iter().skip(max) {
+                    if let Some(status) = data.messages.get(&id).map(|message| message.status)
+                        && is_prunable(retention, status) {
+                        data.messages.remove(&id);
+                        deleted.push((id, status));
+                    }
+                }
+            }
+        }
+
+        let Data { messages, unpublished, positions, .. } = &mut *data;
+        unpublished.retain(|id, _| messages.contains_key(id));
+        positions.retain(|id, _| messages.contains_key(id));
+        Ok(deleted)
+    }
+
+    fn vacuum(&self) -> anyhow::Result<()> {
+        let mut data = self.data.write().unwrap();
+        data.messages.shrink_to_fit();
+        data.unpublished.shrink_to_fit();
+        data.positions.shrink_to_fit();
+        Ok(())
+    }
+
+    fn search_messages(&self, query: &SearchQuery) -> anyhow::Result<SearchPage> {
+        let data = self.data.read().unwrap();
+        let snapshot = query.after.map_or(data.last_position, |after| after.snapshot);
+        let mut results: Vec<SearchResult> = data.messages.values()
+            .filter(|message| data.positions[&message.id] <= snapshot)
+            .filter(|message| query.platform.as_ref().is_none_or(|platform| &message.platform == platform)
+                && query.channel.as_ref().is_none_or(|channel| &message.channel == channel)
+                && query.username.as_ref().is_none_or(|username| message.username.eq_ignore_ascii_case(username))
+                && query.status.is_none_or(|status| message.status == status))
+            .filter_map(|message| search_message(message, &query.terms))
+            .filter(|result| query.after.is_none_or(|after| {
+                result.score > after.score || (result.score == after.score && result.message.id < after.id)
+            }))
+            .collect();
+        results.sort_by(|a, b| a.score.total_cmp(&b.score).then(b.message.id.cmp(&a.message.id)));
+        results.truncate(query.limit);
+        Ok(SearchPage::new(results, query.limit, snapshot))
+    }
+}
+
+/// Whether `word` starts with one of the lowercase search terms.
+fn matches_term(word: &str, terms: &[String]) -> bool {
+    let word = word.to_lowercase();
+    terms.iter().any(|term| word.starts_with(term.as_str()))
+}
+
+/// Scores a message by how many of its words match, negated so that lower is
+/// better like SQLite's bm25. `None` unless every term matches some word.
+fn search_message(message: &ChatMessage, terms: &[String]) -> Option<SearchResult> {
+    let words = |text: &str| text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>();
+    let content_words = words(&message.content);
+    let username_words = words(&message.username);
+    let all_match = terms.iter().all(|term| {
+        content_words.iter().chain(&username_words).any(|word| word.starts_with(term.as_str()))
+    });
+    if !all_match {
+        return None;
+    }
+
+    let matched = content_words.iter().chain(&username_words).filter(|word| matches_term(word, terms)).count();
+    let text = if content_words.iter().any(|word| matches_term(word, terms)) { &message.content } else { &message.username };
+    Some(SearchResult {
+        message: message.clone(),
+        snippet: snippet_html(&mark_matches(text, terms)),
+        score: -(matched as f64),
+    })
+}
+
+/// Wraps the matching words of `text` in `MATCH_START` and `MATCH_END`.
+fn mark_matches(text: &str, terms: &[String]) -> String {
+    let mut marked = String::with_capacity(text.len());
+    let mut word = String::new();
+    let flush = |word: &mut String, marked: &mut String| {
+        if matches_term(word, terms) {
+            marked.push(MATCH_START);
+            marked.push_str(word);
+            marked.push(MATCH_END);
+        } else {
+            marked.push_str(word);
+        }
+        word.clear();
+    };
+    for c in text.chars() {
+        if c.is_alphanumeric() {
+            word.push(c);
+        } else {
+            flush(&mut word, &mut marked);
+            marked.push(c);
+        }
+    }
+    flush(&mut word, &mut marked);
+    marked
+}
@@ -0,0 +1,645 @@
+use std::sync::Mutex;
+
+use rusqlite::OptionalExtension;
+use tracing::warn;
+
+use crate::{
+    config::RetentionConfig,
+    db::{self, ReadPool},
+    models::{
+        Account, AuditAction, AuditEntry, Channel, ChannelId, ChatMessage, MessageFilter, MessageId, MessageStatus, PublishRule,
+        Role, UserList, UserListEntry,
+    },
+};
+
+use super::{
+    edit_audit_entry, is_prunable, snippet_html, status_audit_entry, AuditQuery, ChatStore, SearchPage, SearchQuery, SearchResult,
+    StatusChange, HOUR_MS, MATCH_END, MATCH_START,
+};
+
+const ACCOUNT_COLUMNS: &str = "username, password_hash, role, created_at";
+
+const MESSAGE_COLUMNS: &str = "id, platform, channel, username, content, additional_info, timestamp, published, status, flags, edited_content, published_at";
+
+/// Stores everything in the SQLite database. Writes go through one connection,
+/// reads through a pool so they never wait for a write.
+pub struct SqliteStore {
+    writer: Mutex<rusqlite::Connection>,
+    readers: ReadPool,
+}
+
+impl SqliteStore {
+    /// Opens the store on a database that `migrations::run` already migrated.
+    pub fn open(path: &str, read_connections: usize) -> rusqlite::Result<Self> {
+        Ok(Self {
+            writer: Mutex::new(db::open(path)?),
+            readers: ReadPool::new(path, read_connections),
+        })
+    }
+}
+
+fn message_from_row(row: &rusqlite::Row) -> rusqlite::Result<ChatMessage> {
+    Ok(ChatMessage {
+        id: row.get(0)?,
+        platform: row.get(1)?,
+        channel: row.get(2)?,
+        username: row.get(3)?,
+        content: row.get(4)?,
+        additional_info: row.get(5)?,
+        timestamp: row.get::<_, i64>(6)? as u64,
+        published: row.get::<_, i32>(7)? != 0,
+        status: row.get(8)?,
+        flags: row.get::<_, Option<String>>(9)?
+            .and_then(|flags| serde_json::from_str(&flags).ok())
+            .unwrap_or_default(),
+        edited_content: row.get(10)?,
+        published_at: row.get::<_, Option<i64>>(11)?.map(|published_at| published_at as u64),
+    })
+}
+
+fn insert_message(conn: &rusqlite::Connection, chat_message: &ChatMessage) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT INTO messages (id, platform, channel, username, content, additional_info, timestamp, published, status, flags) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        rusqlite::params![
+            chat_message.id,
+            chat_message.platform,
+            chat_message.channel,
+            chat_message.username,
+            chat_message.content,
+            chat_message.additional_info,
+            chat_message.timestamp as i64,
+            chat_message.published as i32,
+            chat_message.status,
+            (!chat_message.flags.is_empty()).then(|| serde_json::json!(chat_message.flags).to_string())
+        ],
+    )?;
+    Ok(())
+}
+
+fn account_from_row(row: &rusqlite::Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        username: row.get(0)?,
+        password_hash: row.get(1)?,
+        role: row.get(2)?,
+        created_at: row.get::<_, i64>(3)? as u64,
+    })
+}
+
+/// Runs a `DELETE` on messages and returns the id and status of each row it deleted.
+fn delete_messages(
+    conn: &rusqlite::Connection,
+    sql: &str,
+    params: impl rusqlite::Params,
+) -> rusqlite::Result<Vec<(MessageId, MessageStatus)>> {
+    let mut stmt = conn.prepare(&format!("{} RETURNING id, status", sql))?;
+    let deleted = stmt.query_map(params, |row| Ok((row.get(0)?, row.get(1)?)))?;
+    deleted.collect()
+}
+
+fn get_message(conn: &rusqlite::Connection, id: MessageId) -> rusqlite::Result<Option<ChatMessage>> {
+    conn.query_row(
+        &format!("SELECT {} FROM messages WHERE id = ?1", MESSAGE_COLUMNS),
+        rusqlite::params![id],
+        message_from_row,
+    ).optional()
+}
+
+fn add_audit_entry(conn: &rusqlite::Connection, entry: &AuditEntry) -> rusqlite::Result<i64> {
+    conn.execute(
+        "INSERT INTO audit_log (timestamp, actor, action, message_id, platform, channel, before, after)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            entry.timestamp as i64,
+            entry.actor,
+            entry.action,
+            entry.message_id,
+            entry.platform,
+            entry.channel,
+            entry.before,
+            entry.after,
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn add_channel(conn: &rusqlite::Connection, name: &str, platform: &str) -> rusqlite::Result<Option<ChannelId>> {
+    let id = ChannelId::new();
+    let inserted = conn.execute(
+        "INSERT OR IGNORE INTO channels (id, name, platform) VALUES (?1, ?2, ?3)",
+        rusqlite::params![id, name, platform],
+    )?;
+    Ok((inserted > 0).then_some(id))
+}
+
+impl ChatStore for SqliteStore {
+    fn insert_message(&self, chat_message: &ChatMessage) -> anyhow::Result<()> {
+        Ok(insert_message(&self.writer.lock().unwrap(), chat_message)?)
+    }
+
+    fn insert_messages(&self, chat_messages: &[ChatMessage]) -> anyhow::Result<Vec<bool>> {
+        let mut conn = self.writer.lock().unwrap();
+        let tx = conn.transaction()?;
+        let stored = chat_messages.iter()
+            .map(|chat_message| match insert_message(&tx, chat_message) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Failed to insert message {}: {:?}", chat_message.id, e);
+                    false
+                }
+            })
+            .collect();
+        tx.commit()?;
+        Ok(stored)
+    }
+
+    fn get_message(&self, id: MessageId) -> anyhow::Result<Option<ChatMessage>> {
+        Ok(get_message(&*self.readers.get()?, id)?)
+    }
+
+    fn get_messages(&self, limit: usize, before: Option<u64>) -> anyhow::Result<Vec<ChatMessage>> {
+        let mut query = format!("SELECT {} FROM messages", MESSAGE_COLUMNS);
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        if let Some(before_ts) = before {
+            query.push_str(" WHERE timestamp < ?1");
+            params.push(Box::new(before_ts as i64));
+        }
+        query.push_str(" ORDER BY timestamp DESC LIMIT ?");
+        params.push(Box::new(limit as i64));
+
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare(&query)?;
+        let messages = stmt.query_map(rusqlite::params_from_iter(params), message_from_row)?;
+        Ok(messages.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn get_pending_messages(&self) -> anyhow::Result<Vec<ChatMessage>> {
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM messages WHERE status = ?1 ORDER BY timestamp ASC", MESSAGE_COLUMNS))?;
+        let messages = stmt.query_map(rusqlite::params![MessageStatus::Pending], message_from_row)?;
+        Ok(messages.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn get_published_messages(&self, limit: usize, since: Option<u64>) -> anyhow::Result<Vec<ChatMessage>> {
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM messages WHERE status = ?1 AND published_at > ?2 ORDER BY published_at DESC, id DESC LIMIT ?3",
+            MESSAGE_COLUMNS
+        ))?;
+        let mut messages = stmt
+            .query_map(rusqlite::params![MessageStatus::Published, since.unwrap_or(0) as i64, limit as i64], message_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        messages.reverse();
+        Ok(messages)
+    }
+
+    fn set_message_status(&self, id: MessageId, status: MessageStatus, action: AuditAction, actor: &str) -> anyhow::Result<StatusChange> {
+        let mut conn = self.writer.lock().unwrap();
+        let tx = conn.transaction()?;
+        let Some(before) = get_message(&tx, id)? else {
+            return Ok(StatusChange::NotFound);
+        };
+        if !before.status.can_become(status) {
+            return Ok(StatusChange::NotAllowed(before.status));
+        }
+
+        let entry = status_audit_entry(&before, status, action, actor);
+        tx.execute(
+            "UPDATE messages SET status = ?1, published = ?2 WHERE id = ?3",
+            rusqlite::params![status, (status == MessageStatus::Published) as i32, id]
+        )?;
+        if status == MessageStatus::Published {
+            tx.execute(
+                "UPDATE messages SET published_at = ?1 WHERE id = ?2",
+                rusqlite::params![entry.timestamp as i64, id]
+            )?;
+        }
+        if before.status == MessageStatus::Published && status == MessageStatus::Pending {
+            tx.execute(
+                "UPDATE messages SET unpublished_by = ?1, unpublished_at = ?2 WHERE id = ?3",
+                rusqlite::params![actor, entry.timestamp as i64, id]
+            )?;
+        }
+        add_audit_entry(&tx, &entry)?;
+
+        let message = get_message(&tx, id)?
+            .ok_or_else(|| anyhow::anyhow!("Message {} disappeared while updating it", id))?;
+        tx.commit()?;
+        Ok(StatusChange::Changed { from: before.status, message: Box::new(message) })
+    }
+
+    fn set_edited_content(&self, id: MessageId, edited_content: Option<&str>, actor: &str) -> anyhow::Result<Option<ChatMessage>> {
+        let mut conn = self.writer.lock().unwrap();
+        let tx = conn.transaction()?;
+        let Some(before) = get_message(&tx, id)? else {
+            return Ok(None);
+        };
+
+        tx.execute(
+            "UPDATE messages SET edited_content = ?1 WHERE id = ?2",
+            rusqlite::params![edited_content, id]
+        )?;
+        let after = get_message(&tx, id)?;
+        if let Some(after) = &after {
+            add_audit_entry(&tx, &edit_audit_entry(&before, after, actor))?;
+        }
+        tx.commit()?;
+        Ok(after)
+    }
+
+    fn add_channel(&self, name: &str, platform: &str) -> anyhow::Result<Option<ChannelId>> {
+        Ok(add_channel(&self.writer.lock().unwrap(), name, platform)?)
+    }
+
+    fn get_channels(&self) -> anyhow::Result<Vec<Channel>> {
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare("SELECT id, name, platform, listen, last_error FROM channels")?;
+        let channels = stmt.query_map([], |row| {
+            Ok(Channel {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                platform: row.get(2)?,
+                listen: row.get::<_, i32>(3)? != 0,
+                last_error: row.get(4)?,
+            })
+        })?;
+        Ok(channels.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn set_channel_listen(&self, platform: &str, name: &str, listen: bool) -> anyhow::Result<()> {
+        let conn = self.writer.lock().unwrap();
+        let updated = conn.execute(
+            "UPDATE channels SET listen = ?1 WHERE name = ?2 AND platform = ?3",
+            rusqlite::params![listen as i32, name, platform],
+        )?;
+
+        if updated == 0 && listen && let Some(id) = add_channel(&conn, name, platform)? {
+            conn.execute(
+                "UPDATE channels SET listen = 1 WHERE id = ?1",
+                rusqlite::params![id],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn set_channel_error(&self, platform: &str, name: &str, error: Option<&str>) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            "UPDATE channels SET last_error = ?1 WHERE name = ?2 AND platform = ?3",
+            rusqlite::params![error, name, platform],
+        )?;
+        Ok(())
+    }
+
+    fn delete_channel(&self, platform: &str, name: &str) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            "DELETE FROM channels WHERE name = ?1 AND platform = ?2",
+            rusqlite::params![name, platform],
+        )?;
+        Ok(())
+    }
+
+    fn get_setting(&self, key: &str) -> anyhow::Result<Option<String>> {
+        let conn = self.readers.get()?;
+        Ok(conn.query_row(
+            "SELECT value FROM settings WHERE key = ?1",
+            rusqlite::params![key],
+            |row| row.get(0),
+        ).optional()?)
+    }
+
+    fn set_setting(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
+            rusqlite::params![key, value],
+        )?;
+        Ok(())
+    }
+
+    fn add_audit_entry(&self, entry: &AuditEntry) -> anyhow::Result<i64> {
+        Ok(add_audit_entry(&self.writer.lock().unwrap(), entry)?)
+    }
+
+    fn get_audit_log(&self, query: &AuditQuery) -> anyhow::Result<Vec<AuditEntry>> {
+        let mut conditions = Vec::new();
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
+        if let Some(before) = query.before {
+            conditions.push("id < ?");
+            params.push(Box::new(before));
+        }
+        if let Some(actor) = &query.actor {
+            conditions.push("actor = ?");
+            params.push(Box::new(actor.clone()));
+        }
+        if let Some(action) = query.action {
+            conditions.push("action = ?");
+            params.push(Box::new(action));
+        }
+        if let Some(message_id) = query.message_id {
+            conditions.push("message_id = ?");
+            params.push(Box::new(message_id));
+        }
+
+        let mut sql = "SELECT id, timestamp, actor, action, message_id, platform, channel, before, after FROM audit_log".to_string();
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+        sql.push_str(" ORDER BY id DESC LIMIT ?");
+        params.push(Box::new(query.limit as i64));
+
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare(&sql)?;
+        let entries = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                timestamp: row.get::<_, i64>(1)? as u64,
+                actor: row.get(2)?,
+                action: row.get(3)?,
+                message_id: row.get(4)?,
+                platform: row.get(5)?,
+                channel: row.get(6)?,
+                before: row.get(7)?,
+                after: row.get(8)?,
+            })
+        })?;
+        Ok(entries.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn get_accounts(&self) -> anyhow::Result<Vec<Account>> {
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM accounts ORDER BY created_at", ACCOUNT_COLUMNS))?;
+        let accounts = stmt.query_map([], account_from_row)?;
+        Ok(accounts.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn get_account(&self, username: &str) -> anyhow::Result<Option<Account>> {
+        let conn = self.readers.get()?;
+        Ok(conn.query_row(
+            &format!("SELECT {} FROM accounts WHERE username = ?1", ACCOUNT_COLUMNS),
+            rusqlite::params![username],
+            account_from_row,
+        ).optional()?)
+    }
+
+    fn save_account(&self, account: &Account) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            &format!("INSERT OR REPLACE INTO accounts ({}) VALUES (?1, ?2, ?3, ?4)", ACCOUNT_COLUMNS),
+            rusqlite::params![account.username, account.password_hash, account.role, account.created_at as i64],
+        )?;
+        Ok(())
+    }
+
+    fn delete_account(&self, username: &str) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            "DELETE FROM accounts WHERE username = ?1",
+            rusqlite::params![username],
+        )?;
+        Ok(())
+    }
+
+    fn count_accounts(&self, role: Option<Role>) -> anyhow::Result<usize> {
+        let conn = self.readers.get()?;
+        let count: i64 = match role {
+            Some(role) => conn.query_row("SELECT COUNT(*) FROM accounts WHERE role = ?1", rusqlite::params![role], |row| row.get(0))?,
+            None => conn.query_row("SELECT COUNT(*) FROM accounts", [], |row| row.get(0))?,
+        };
+        Ok(count as usize)
+    }
+
+    fn get_publish_rules(&self) -> anyhow::Result<Vec<PublishRule>> {
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare("SELECT rule FROM publish_rules")?;
+        let rules = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .map(|rule| Ok(serde_json::from_str(&rule?)?))
+            .collect::<anyhow::Result<Vec<PublishRule>>>()?;
+        Ok(rules)
+    }
+
+    fn save_publish_rule(&self, rule: &PublishRule) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            "INSERT INTO publish_rules (platform, channel, rule) VALUES (?1, ?2, ?3)
+                ON CONFLICT (platform, channel) DO UPDATE SET rule = excluded.rule",
+            rusqlite::params![rule.platform, rule.channel, serde_json::to_string(rule)?],
+        )?;
+        Ok(())
+    }
+
+    fn delete_publish_rule(&self, platform: &str, channel: &str) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            "DELETE FROM publish_rules WHERE platform = ?1 AND channel = ?2",
+            rusqlite::params![platform, channel],
+        )?;
+        Ok(())
+    }
+
+    fn get_filters(&self) -> anyhow::Result<Vec<MessageFilter>> {
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare("SELECT id, filter FROM message_filters ORDER BY id")?;
+        let filters = stmt
+            .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?)))?
+            .map(|row| {
+                let (id, filter) = row?;
+                let mut filter: MessageFilter = serde_json::from_str(&filter)?;
+                filter.id = Some(id);
+                Ok(filter)
+            })
+            .collect::<anyhow::Result<Vec<MessageFilter>>>()?;
+        Ok(filters)
+    }
+
+    fn add_filter(&self, filter: &MessageFilter) -> anyhow::Result<i64> {
+        let conn = self.writer.lock().unwrap();
+        conn.execute(
+            "INSERT INTO message_filters (filter) VALUES (?1)",
+            rusqlite::params![serde_json::to_string(filter)?],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    fn delete_filter(&self, id: i64) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            "DELETE FROM message_filters WHERE id = ?1",
+            rusqlite::params![id],
+        )?;
+        Ok(())
+    }
+
+    fn get_user_list(&self, list: UserList) -> anyhow::Result<Vec<UserListEntry>> {
+        let conn = self.readers.get()?;
+        let mut stmt = conn.prepare(&format!("SELECT platform, user_id, username, added_at FROM {} ORDER BY added_at DESC", list.table()))?;
+        let entries = stmt.query_map([], |row| {
+            Ok(UserListEntry {
+                platform: row.get(0)?,
+                user_id: row.get(1)?,
+                username: row.get(2)?,
+                added_at: row.get::<_, i64>(3)? as u64,
+            })
+        })?;
+        Ok(entries.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn add_user_to_list(&self, list: UserList, entry: &UserListEntry) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            &format!("INSERT OR REPLACE INTO {} (platform, user_id, username, added_at) VALUES (?1, ?2, ?3, ?4)", list.table()),
+            rusqlite::params![entry.platform, entry.user_id, entry.username, entry.added_at as i64],
+        )?;
+        Ok(())
+    }
+
+    fn remove_user_from_list(&self, list: UserList, platform: &str, user_id: &str) -> anyhow::Result<()> {
+        self.writer.lock().unwrap().execute(
+            &format!("DELETE FROM {} WHERE platform = ?1 AND user_id = ?2", list.table()),
+            rusqlite::params![platform, user_id],
+        )?;
+        Ok(())
+    }
+
+    fn expire_pending(&self, retention: &RetentionConfig, now: u64) -> anyhow::Result<Vec<ChatMessage>> {
+        let Some(hours) = retention.pending_max_age_hours else {
+            return Ok(Vec::new());
+        };
+
+        let conn = self.writer.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "UPDATE messages SET status = ?1 WHERE status = ?2 AND timestamp < ?3 RETURNING {}",
+            MESSAGE_COLUMNS
+        ))?;
+        let expired = stmt.query_map(
+            rusqlite::params![MessageStatus::Expired, MessageStatus::Pending, now.saturating_sub(hours * HOUR_MS) as i64],
+            message_from_row,
+        )?;
+        Ok(expired.collect::<rusqlite::Result<_>>()?)
+    }
+
+    fn prune(&self, retention: &RetentionConfig, now: u64) -> anyhow::Result<Vec<(MessageId, MessageStatus)>> {
+        let prunable = [MessageStatus::Pending, MessageStatus::Published, MessageStatus::Rejected, MessageStatus::Expired]
+            .into_iter()
+            .filter(|status| is_prunable(retention, *status))
+            .map(|status| format!("'{}'", status.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let mut conn = self.writer.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut deleted = Vec::new();
+
+        if let Some(hours) = retention.max_age_hours {
+            deleted.extend(delete_messages(
+                &tx,
+                &format!("DELETE FROM messages WHERE status IN ({}) AND timestamp < ?1", prunable),
+                rusqlite::params![now.saturating_sub(hours * HOUR_MS) as i64],
+            )?);
+        }
+        if let Some(hours) = retention.rejected_max_age_hours {
+            deleted.extend(delete_messages(
+                &tx,
+                "DELETE FROM messages WHERE status = ?1 AND timestamp < ?2",
+                rusqlite::params![MessageStatus::Rejected, now.saturating_sub(hours * HOUR_MS) as i64],
+            )?);
+        }
+        if let Some(max) = retention.max_messages_per_channel {
+            // Kept messages count towards the limit, so a channel never holds
+            // more than `max` messages unless they are all kept.
+            deleted.extend(delete_messages(
+                &tx,
+                &format!(
+                    "DELETE FROM messages WHERE status IN ({}) AND rowid IN (
+                        SELECT rowid FROM (
+                            SELECT rowid, ROW_NUMBER() OVER (PARTITION BY platform, channel ORDER BY timestamp DESC, id DESC) AS position
+                            FROM messages
+                        ) WHERE position > ?1
+                    )",
+                    prunable
+                ),
+                rusqlite::params![max as i64],
+            )?);
+        }
+
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    fn vacuum(&self) -> anyhow::Result<()> {
+        let conn = self.writer.lock().unwrap();
+        conn.execute_batch("VACUUM")?;
+        // VACUUM may renumber the message rowids the search index refers to.
+        conn.execute("INSERT INTO messages_fts (messages_fts) VALUES ('rebuild')", [])?;
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE)")?;
+        Ok(())
+    }
+
+    fn search_messages(&self, query: &SearchQuery) -> anyhow::Result<SearchPage> {
+        let conn = self.readers.get()?;
+        let snapshot = match query.after {
+            Some(after) => after.snapshot,
+            None => conn.query_row("SELECT COALESCE(MAX(rowid), 0) FROM messages", [], |row| row.get::<_, i64>(0))? as u64,
+        };
+
+        // Every term as a quoted prefix, so "hel" finds "hello".
+        let fts_query = query.terms.iter()
+            .map(|term| format!("\"{}\"*", term.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let mut conditions = vec!["messages_fts MATCH ?", "messages.rowid <= ?"];
+        let mut params: Vec<Box<dyn rusqlite::ToSql>> = vec![
+            Box::new(MATCH_START.to_string()),
+            Box::new(MATCH_END.to_string()),
+            Box::new(fts_query),
+            Box::new(snapshot as i64),
+        ];
+        if let Some(platform) = &query.platform {
+            conditions.push("platform = ?");
+            params.push(Box::new(platform.clone()));
+        }
+        if let Some(channel) = &query.channel {
+            conditions.push("channel = ?");
+            params.push(Box::new(channel.clone()));
+        }
+        if let Some(username) = &query.username {
+            conditions.push("messages.username = ? COLLATE NOCASE");
+            params.push(Box::new(username.clone()));
+        }
+        if let Some(status) = query.status {
+            conditions.push("status = ?");
+            params.push(Box::new(status));
+        }
+
+        let mut sql = format!(
+            "WITH results AS (
+                SELECT {}, snippet(messages_fts, -1, ?, ?, '…', 16) AS snippet, bm25(messages_fts) AS score
+                FROM messages_fts JOIN messages ON messages.rowid = messages_fts.rowid
+                WHERE {}
+            )",
+            MESSAGE_COLUMNS.split(", ").map(|column| format!("messages.{}", column)).collect::<Vec<_>>().join(", "),
+            conditions.join(" AND ")
+        );
+        if let Some(after) = query.after {
+            // bm25 weighs terms by how many messages contain them, so scores
+            // change as messages arrive. Compare with the cursor message's
+            // current score rather than the one it had on the previous page.
+            sql.push_str(
+                ", cursor AS (SELECT COALESCE((SELECT score FROM results WHERE id = ?), ?) AS score)
+                SELECT results.* FROM results, cursor
+                WHERE results.score > cursor.score OR (results.score = cursor.score AND results.id < ?)"
+            );
+            params.push(Box::new(after.id));
+            params.push(Box::new(after.score));
+            params.push(Box::new(after.id));
+        } else {
+            sql.push_str(" SELECT * FROM results");
+        }
+        sql.push_str(" ORDER BY score, id DESC LIMIT ?");
+        params.push(Box::new(query.limit as i64));
+
+        let mut stmt = conn.prepare(&sql)?;
+        let results = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+            Ok(SearchResult {
+                message: message_from_row(row)?,
+                snippet: snippet_html(&row.get::<_, String>(12)?),
+                score: row.get(13)?,
+            })
+        })?;
+        Ok(SearchPage::new(results.collect::<rusqlite::Result<_>>()?, query.limit, snapshot))
+    }
+}
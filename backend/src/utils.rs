@@ -1,329 +1,80 @@
-use std::{collections::HashMap, sync::{Arc, Mutex}};
-
-use brainrot::{twitch, youtube::{self, Action, ChatItem}, TwitchChat, TwitchChatEvent};
-use clap::Parser;
-use futures_util::StreamExt;
-use tracing::{info, warn};
-
-use crate::models::Args;
-
-
-pub fn initialize_db() -> rusqlite::Connection {
-    let conn = rusqlite::Connection::open("chat_messages.db").expect("Failed to open DB");
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS messages (
-            id BLOB,
-            platform TEXT NOT NULL,
-            channel TEXT NOT NULL,
-            username TEXT NOT NULL,
-            content TEXT NOT NULL,
-            additional_info TEXT,
-            timestamp INTEGER NOT NULL,
-            published INTEGER NOT NULL DEFAULT 0
-        )",
-        [],
-    ).expect("Failed to create table");
-
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS channels (
-            id blob PRIMARY KEY,
-            name TEXT NOT NULL,
-            platform TEXT NOT NULL,
-            listen INTEGER NOT NULL DEFAULT 0
-        )",
-        [],
-    ).expect("Failed to create channels table");
-
-    conn
-}
-
-pub fn add_channel(conn: &rusqlite::Connection, name: &str, platform: &str) -> rusqlite::Result<uuid::Uuid> {
-    let id = uuid::Uuid::now_v7();
-    conn.execute(
-        "INSERT INTO channels (id, name, platform) VALUES (?1, ?2, ?3)",
-        rusqlite::params![id.as_bytes(), name, platform],
-    )?;
-    Ok(id)
-}
-
-pub fn get_channels(conn: &rusqlite::Connection) -> rusqlite::Result<Vec<crate::models::Channel>> {
-    let mut stmt = conn.prepare("SELECT id, name, platform, listen FROM channels")?;
-    
-    let channel_iter = stmt.query_map([], |row| {
-        Ok(crate::models::Channel {
-            id: row.get::<_, Vec<u8>>(0)?.as_slice().try_into().map(u128::from_le_bytes).unwrap_or(0).to_string(),
-            name: row.get(1)?,
-            platform: row.get(2)?,
-            listen: row.get::<_, i32>(3)? != 0,
-        })
-    })?;
-
-    let mut channels = Vec::new();
-    for channel in channel_iter {
-        channels.push(channel?);
+use crate::store::{ChatStore, StatusChange};
+use crate::protocol::{AdminEvent, ClientEvent};
+use crate::models::{AppState, AuditAction, ChatMessage, MessageId, MessageStatus, UserList, UserLists};
+
+/// Loads both user lists into the in-memory copy kept on `AppState`.
+pub fn get_user_lists(store: &dyn ChatStore) -> anyhow::Result<UserLists> {
+    let mut user_lists = UserLists::default();
+    for list in [UserList::Trusted, UserList::Blocked] {
+        for entry in store.get_user_list(list)? {
+            user_lists.get_mut(list).insert((entry.platform, entry.user_id));
+        }
     }
-    Ok(channels)
+    Ok(user_lists)
 }
 
-pub async fn listen_to_twitch(
-    name: String,
-    admin_panel_sender: tokio::sync::broadcast::Sender<crate::models::ChatMessage>,
-    db_conn: Arc<Mutex<rusqlite::Connection>>,
-    listened_channels: Arc<Mutex<HashMap<String, HashMap<String, tokio::task::JoinHandle<()>>>>>,
-) -> anyhow::Result<()> {
-
-    if listened_channels.lock().unwrap().contains_key("twitch") &&
-        listened_channels.lock().unwrap().get("twitch").unwrap().contains_key(&name) {
-        info!("Already listening to Twitch channel: {}", &name);
-        return Ok(());
-    }
-
-    let name_for_handler = name.clone();
-    let mut client = TwitchChat::new(name_for_handler.clone(), twitch::Anonymous).await?;
-    
-    let handler = tokio::spawn(async move {
-        while let Some(event) = client.next().await {
-            match event {
-                Ok(TwitchChatEvent::Message { user, contents, .. }) => {
-                    let content = contents.iter().map(|c| c.to_string()).collect::<String>();
-
-                    info!("Twitch message from {}: {}", user.display_name, content);
-
-                    let chat_message = crate::models::ChatMessage {
-                        id: uuid::Uuid::now_v7().as_u128().to_string(),
-                        platform: "twitch".to_string(),
-                        channel: name_for_handler.clone(),
-                        username: user.display_name.clone(),
-                        content: content.clone(),
-                        timestamp: chrono::Utc::now().timestamp_millis() as u64,
-                        published: false,
-                        additional_info: Some(serde_json::json!({
-                            "username": user.username,
-                            "id": user.id,
-                            "display_color": user.display_color,
-                            "sub_months": user.sub_months.map(|v| v.get()),
-                            "role": format!("{:?}", user.role),
-                            "returning_chatter": user.returning_chatter,
-                        }).to_string()),
-                    };
-
-                    {
-                        let conn = db_conn.lock().unwrap();
-                        conn.execute(
-                            "INSERT INTO messages (id, platform, channel, username, content, additional_info, timestamp, published) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 0)",
-                            rusqlite::params![
-                                chat_message.id.parse::<u128>().unwrap().to_le_bytes(),
-                                chat_message.platform,
-                                chat_message.channel,
-                                chat_message.username,
-                                chat_message.content,
-                                chat_message.additional_info,
-                                chat_message.timestamp as i64
-                            ],
-                        ).expect("Failed to insert message");
-                    }
-
-                    let _ = admin_panel_sender.send(chat_message);
-                }
-                Ok(_) => {}
-                Err(e) => eprintln!("Error receiving Twitch message: {:?}", e),
-            }
+/// Moves a message to a new status, recording `action` by `actor` in the audit
+/// log, and notifies the admin panel once that is stored. Overlays are told to
+/// remove a message that is no longer published, and to unpin it if it was
+/// pinned. Nothing changes if the message's status cannot become `status`.
+pub fn set_message_status(
+    state: &AppState,
+    message_id: MessageId,
+    status: MessageStatus,
+    action: AuditAction,
+    actor: &str,
+) -> anyhow::Result<StatusChange> {
+    let change = state.store.set_message_status(message_id, status, action, actor)?;
+    if let StatusChange::Changed { from, message } = &change {
+        state.admin_panel_sender.send(AdminEvent::MessageUpdated((**message).clone()));
+        if *from == MessageStatus::Published && message.status != MessageStatus::Published {
+            let _ = state.client_sender.send(ClientEvent::MessageRetracted { id: message.id });
+            unpin_message(state, message.id);
         }
-    });
-
-    listened_channels.lock().unwrap()
-        .entry("twitch".to_string())
-        .or_insert_with(HashMap::new)
-        .insert(name.clone(), handler);
-
-    Ok(())
-}
-
-pub fn delete_channel(conn: &rusqlite::Connection, platform: &str, name: &str) -> rusqlite::Result<()> {
-    conn.execute(
-        "DELETE FROM channels WHERE name = ?1 AND platform = ?2",
-        rusqlite::params![name, platform],
-    )?;
-    Ok(())
-}
-
-pub fn get_messages(
-    limit: usize,
-    before: Option<u64>,
-    conn: &rusqlite::Connection
-) -> rusqlite::Result<Vec<crate::models::ChatMessage>> {
-    let mut query = "SELECT id, platform, channel, username, content, additional_info, timestamp, published FROM messages".to_string();
-    let mut params: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
-    if let Some(before_ts) = before {
-        query.push_str(" WHERE timestamp < ?1");
-        params.push(Box::new(before_ts as i64));
     }
-    query.push_str(" ORDER BY timestamp DESC LIMIT ?");
-    params.push(Box::new(limit as i64));
-
-    let mut stmt = conn.prepare(&query)?;
-    let message_iter = stmt.query_map(rusqlite::params_from_iter(params), |row| {
-        Ok(crate::models::ChatMessage {
-            id: row.get::<_, Vec<u8>>(0)?.as_slice().try_into().map(u128::from_le_bytes).unwrap_or(0).to_string(),
-            platform: row.get(1)?,
-            channel: row.get(2)?,
-            username: row.get(3)?,
-            content: row.get(4)?,
-            additional_info: row.get(5)?,
-            timestamp: row.get::<_, i64>(6)? as u64,
-            published: row.get::<_, i32>(7)? != 0,
-        })
-    })?;
+    Ok(change)
+}
 
-    let mut messages = Vec::new();
-    for message in message_iter {
-        messages.push(message?);
+/// Unpins the message if it is the pinned one and tells overlays.
+pub fn unpin_message(state: &AppState, message_id: MessageId) {
+    let mut pinned = state.pinned_message.lock().unwrap();
+    if pinned.as_ref().is_some_and(|pinned| pinned.id == message_id) {
+        *pinned = None;
+        let _ = state.client_sender.send(ClientEvent::MessageUnpinned { id: message_id });
     }
-
-    Ok(messages)
 }
 
-pub async fn listen_to_youtube(
-    name: String,
-    admin_panel_sender: tokio::sync::broadcast::Sender<crate::models::ChatMessage>,
-    db_conn: Arc<Mutex<rusqlite::Connection>>,
-    listened_channels: Arc<Mutex<HashMap<String, HashMap<String, tokio::task::JoinHandle<()>>>>>,
-) -> anyhow::Result<()> {
-
-    if listened_channels.lock().unwrap().contains_key("youtube") &&
-        listened_channels.lock().unwrap().get("youtube").unwrap().contains_key(&name) {
-        info!("Already listening to YouTube channel: {}", name);
-        return Ok(());
+pub fn publish_message(state: &AppState, message_id: MessageId, actor: &str) -> anyhow::Result<StatusChange> {
+    let change = set_message_status(state, message_id, MessageStatus::Published, AuditAction::Publish, actor)?;
+    if let StatusChange::Changed { message, .. } = &change {
+        let _ = state.client_sender.send(ClientEvent::MessagePublished(message.for_overlay()));
     }
-
-    let name_for_handler = name.to_string();
-    let context = youtube::ChatContext::new_from_channel(&name_for_handler.clone(), youtube::ChannelSearchOptions::LatestLiveOrUpcoming).await?;
-
-    let handler = tokio::spawn(async move {
-        match youtube::stream(&context).await {
-            Ok(mut stream) => {
-                
-                while let Some(Ok(c)) = stream.next().await {
-                    if let Action::AddChatItem {
-                        item: ChatItem::TextMessage { message_renderer_base, message },
-                        ..
-                    } = c
-                    {
-                        let chat_message = crate::models::ChatMessage {
-                            id: uuid::Uuid::now_v7().as_u128().to_string(),
-                            platform: "youtube".to_string(),
-                            channel: name_for_handler.clone(),
-                            username: {
-                                match &message_renderer_base.author_name {
-                                    Some(name) => name.simple_text.clone(),
-                                    None => "unknown".to_string()
-                                }
-                            },
-                            content: {
-                                match &message {
-                                    Some(msg) => msg.runs.iter().map(|run| run.to_chat_string()).collect::<String>(),
-                                    None => "".to_string()
-                                }
-                            },
-                            timestamp: chrono::Utc::now().timestamp_millis() as u64,
-                            published: false,
-                            additional_info: None,
-                        };
-
-                        info!("YouTube message from {}: {}", chat_message.username, chat_message.content);
-
-                        {
-                            let conn = db_conn.lock().unwrap();
-                            conn.execute(
-                                "INSERT INTO messages (id, platform, channel, username, content, additional_info, timestamp, published) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, 0)",
-                                rusqlite::params![
-                                    chat_message.id.parse::<u128>().unwrap().to_le_bytes(),
-                                    chat_message.platform,
-                                    chat_message.channel,
-                                    chat_message.username,
-                                    chat_message.content,
-                                    chat_message.additional_info,
-                                    chat_message.timestamp as i64
-                                ],
-                            ).expect("Failed to insert message");
-                        }
-
-                        let _ = admin_panel_sender.send(chat_message);
-                    }
-                }
-            }
-            Err(e) => {
-                eprintln!("Error creating YouTube stream: {:?}", e);
-            }
-        }
-    });
-
-    listened_channels.lock().unwrap()
-        .entry("youtube".to_string())
-        .or_insert_with(HashMap::new)
-        .insert(name.to_string(), handler);
-
-    Ok(())
-
+    Ok(change)
 }
 
-pub fn publish_message(
-    conn: &rusqlite::Connection, 
-    message_id: u128,
-    client_sender: &tokio::sync::broadcast::Sender<crate::models::ChatMessage>
-) -> rusqlite::Result<()> {
-    let bytes = message_id.to_le_bytes();
-    conn.execute(
-        "UPDATE messages SET published = 1 WHERE id = ?1",
-        rusqlite::params![bytes]
-    )?;
-    
-    let mut stmt = conn.prepare("SELECT id, platform, channel, username, content, additional_info, timestamp, published FROM messages WHERE id = ?1")?;
-    let mut rows = stmt.query(rusqlite::params![bytes])?;
-    if let Some(row) = rows.next()? {
-        let chat_message = crate::models::ChatMessage {
-            id: row.get::<_, Vec<u8>>(0)?.as_slice().try_into().map(u128::from_le_bytes).unwrap_or(0).to_string(),
-            platform: row.get(1)?,
-            channel: row.get(2)?,
-            username: row.get(3)?,
-            content: row.get(4)?,
-            additional_info: row.get(5)?,
-            timestamp: row.get::<_, i64>(6)? as u64,
-            published: row.get::<_, i32>(7)? != 0,
-        };
-        let _ = client_sender.send(chat_message);
-    }
+/// Takes a published message back to the pending queue.
+pub fn unpublish_message(state: &AppState, message_id: MessageId, moderator: &str) -> anyhow::Result<StatusChange> {
+    set_message_status(state, message_id, MessageStatus::Pending, AuditAction::Unpublish, moderator)
+}
 
-    Ok(())
+pub fn reject_message(state: &AppState, message_id: MessageId, actor: &str) -> anyhow::Result<StatusChange> {
+    set_message_status(state, message_id, MessageStatus::Rejected, AuditAction::Reject, actor)
 }
 
-pub fn stop_listening_to_channel(
-    platform: &str,
-    name: &str,
-    listened_channels: Arc<Mutex<HashMap<String, HashMap<String, tokio::task::JoinHandle<()>>>>>,
-) -> anyhow::Result<()> {
-    let mut channels = listened_channels.lock().unwrap();
-    if let Some(platform_map) = channels.get_mut(platform) {
-        if let Some(handle) = platform_map.remove(name) {
-            handle.abort();
-            info!("Stopped listening to {} channel: {}", platform, name);
-        } else {
-            info!("No active listener found for {} channel: {}", platform, name);
+/// Sets the text shown instead of the original content, or restores the
+/// original with `None`. Overlays are updated if the message is already published.
+pub fn edit_message(
+    state: &AppState,
+    message_id: MessageId,
+    edited_content: Option<&str>,
+    actor: &str,
+) -> anyhow::Result<Option<ChatMessage>> {
+    let chat_message = state.store.set_edited_content(message_id, edited_content, actor)?;
+    if let Some(chat_message) = &chat_message {
+        state.admin_panel_sender.send(AdminEvent::MessageUpdated(chat_message.clone()));
+        if chat_message.status == MessageStatus::Published {
+            let _ = state.client_sender.send(ClientEvent::MessageEdited(chat_message.for_overlay()));
         }
-    } else {
-        info!("No active listeners for platform: {}", platform);
     }
-    Ok(())
+    Ok(chat_message)
 }
-
-pub fn parse_args() -> Args {
-    let mut args = Args::parse();
-
-    if args.host.to_lowercase() == "localhost" {
-        warn!("host is localhost; using 127.0.0.1");
-        args.host = "127.0.0.1".to_string();
-    }
-
-    info!("Starting server on http://{}:{}", args.host, args.port);
-    args
-}
\ No newline at end of file
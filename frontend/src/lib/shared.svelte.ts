@@ -7,6 +7,19 @@ export interface Message {
     additional_info: string | null;
     timestamp: number;
     published: boolean;
+    status: 'pending' | 'published' | 'rejected' | 'expired';
+    flags: string[];
+    edited_content?: string | null;
+}
+
+export interface ListenerStatus {
+    platform: string;
+    channel: string;
+    state: 'connecting' | 'live' | 'reconnecting' | 'waiting-for-stream' | 'failed';
+    message_count: number;
+    last_message_at: number | null;
+    last_error: string | null;
+    updated_at: number;
 }
 
 export const message_queue: Array<Message> = $state([]);
@@ -15,7 +28,15 @@ const message_queue_ids: Set<string> = $derived(new Set(message_queue.map(msg =>
 export const published_messages: Array<Message> = $state([]);
 const published_message_ids: Set<string> = $derived(new Set(published_messages.map(msg => msg.id)));
 
+export const listener_statuses: Record<string, ListenerStatus> = $state({});
+
+/** Frame format version the backend sends on its websockets, see backend/src/protocol.rs. */
+const PROTOCOL_VERSION = 1;
+
 export let websocket: WebSocket | null = null;
+/** Stream id and sequence number of the last admin event, to resume from after a reconnect. */
+let last_stream: string | null = null;
+let last_seq: number | null = null;
 
 export function openWebsocket() {
     if (websocket) {
@@ -23,7 +44,10 @@ export function openWebsocket() {
     }
 
     const host = window.location.host;
-    websocket = new WebSocket(`ws://${host}/api/admin/ws`);
+    const since = last_stream !== null && last_seq !== null
+        ? `?stream=${encodeURIComponent(last_stream)}&since=${last_seq}`
+        : '';
+    websocket = new WebSocket(`ws://${host}/api/admin/ws${since}`);
     
     websocket.onopen = () => {
         console.log("WebSocket connection opened");
@@ -42,6 +66,76 @@ export function openWebsocket() {
     }
 }
 
+export function sendCommand(command: Record<string, unknown>) {
+    if (!websocket || websocket.readyState !== WebSocket.OPEN) {
+        throw new Error("WebSocket is not connected");
+    }
+    websocket.send(JSON.stringify(command));
+}
+
+export type Role = 'viewer' | 'moderator' | 'owner';
+
+const ROLE_RANK: Record<Role, number> = { viewer: 0, moderator: 1, owner: 2 };
+
+/** The logged in admin, or null before login. `checked` is false until the first session check. */
+export const session: { username: string | null; role: Role | null; checked: boolean } = $state({ username: null, role: null, checked: false });
+
+/** Whether the logged in admin has at least `role`. */
+export function hasRole(role: Role): boolean {
+    return session.role !== null && ROLE_RANK[session.role] >= ROLE_RANK[role];
+}
+
+export async function checkSession() {
+    const response = await fetch('/api/auth/session');
+    const data = response.ok ? await response.json() : null;
+    session.username = data?.username ?? null;
+    session.role = data?.role ?? null;
+    session.checked = true;
+}
+
+export async function login(username: string, password: string) {
+    const response = await fetch('/api/auth/login', {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ username, password })
+    });
+    if (!response.ok) {
+        throw new Error(`Failed to log in: ${response.statusText}`);
+    }
+    const data = await response.json();
+    session.username = data.username;
+    session.role = data.role;
+}
+
+export async function logout() {
+    await fetch('/api/auth/logout', { method: 'POST' });
+    session.username = null;
+    session.role = null;
+    websocket?.close();
+}
+
+export async function getOverlayToken(): Promise<string> {
+    const response = await fetch('/api/auth/overlay-token');
+    if (!response.ok) {
+        throw new Error(`Failed to fetch overlay token: ${response.statusText}`);
+    }
+    const data = await response.json();
+    return data.token;
+}
+
+export async function rotateOverlayToken(): Promise<string> {
+    const response = await fetch('/api/auth/overlay-token', { method: 'POST' });
+    if (!response.ok) {
+        throw new Error(`Failed to rotate overlay token: ${response.statusText}`);
+    }
+    const data = await response.json();
+    return data.token;
+}
+
+export function clearScreen() {
+    sendCommand({ command: 'clear_screen' });
+}
+
 export async function getChannels() {
     const response = await fetch('/api/channels');
     if (!response.ok) {
@@ -51,6 +145,49 @@ export async function getChannels() {
     return data.channels;
 }
 
+export interface PublishRule {
+    platform: string;
+    channel: string;
+    mode: 'manual' | 'all' | 'roles' | 'delay';
+    roles?: string[];
+    min_sub_months?: number | null;
+    delay_ms?: number;
+}
+
+export async function getRules(): Promise<Array<PublishRule>> {
+    const response = await fetch('/api/rules');
+    if (!response.ok) {
+        throw new Error(`Failed to fetch rules: ${response.statusText}`);
+    }
+    const data = await response.json();
+    return data.rules;
+}
+
+export async function saveRule(platform: string, channel: string, rule: Omit<PublishRule, 'platform' | 'channel'>) {
+    const response = await fetch(`/api/rules/${platform}/${channel}`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify(rule)
+    });
+    if (!response.ok) {
+        throw new Error(`Failed to save rule: ${response.statusText}`);
+    }
+    const data = await response.json();
+    return data.rule;
+}
+
+export async function getListeners() {
+    const response = await fetch('/api/listeners');
+    if (!response.ok) {
+        throw new Error(`Failed to fetch listeners: ${response.statusText}`);
+    }
+    const data = await response.json();
+    data.listeners.forEach((status: ListenerStatus) => {
+        listener_statuses[`${status.platform}/${status.channel}`] = status;
+    });
+    return data.listeners;
+}
+
 export async function publishMessage(id: string) {
     const response = await fetch(`/api/publish/${id}`, {
         method: 'POST'
@@ -64,6 +201,78 @@ export async function publishMessage(id: string) {
     return data;
 }
 
+export async function rejectMessage(id: string) {
+    const response = await fetch(`/api/reject/${id}`, {
+        method: 'POST'
+    });
+    if (!response.ok) {
+        throw new Error(`Failed to reject message: ${response.statusText}`);
+    }
+    removeFromQueue(id);
+    const data = await response.json();
+    return data;
+}
+
+/** Shows `content` instead of the original text; null restores the original. */
+export async function editMessage(id: string, content: string | null) {
+    const response = await fetch(`/api/edit/${id}`, {
+        method: 'POST',
+        headers: { 'Content-Type': 'application/json' },
+        body: JSON.stringify({ content })
+    });
+    if (!response.ok) {
+        throw new Error(`Failed to edit message: ${response.statusText}`);
+    }
+    const data = await response.json();
+    return data;
+}
+
+export async function unpublishMessage(id: string) {
+    const response = await fetch(`/api/unpublish/${id}`, {
+        method: 'POST'
+    });
+    if (!response.ok) {
+        throw new Error(`Failed to unpublish message: ${response.statusText}`);
+    }
+    const data = await response.json();
+    return data;
+}
+
+export async function addUserToList(list: 'trusted' | 'blocked', message: Message) {
+    const user_id = getUserId(message);
+    if (!user_id) {
+        throw new Error(`Message ${message.id} has no user id`);
+    }
+    const username = encodeURIComponent(message.username);
+    const response = await fetch(`/api/users/${list}/${message.platform}/${user_id}?username=${username}`, {
+        method: 'POST'
+    });
+    if (!response.ok) {
+        throw new Error(`Failed to add user to ${list} list: ${response.statusText}`);
+    }
+    const data = await response.json();
+    return data.user;
+}
+
+export function getUserId(message: Message): string | null {
+    if (message.additional_info) {
+        try {
+            const info = JSON.parse(message.additional_info);
+            return info.id != null ? String(info.id) : null;
+        } catch (e) {
+            console.error('Failed to parse additional_info:', e);
+        }
+    }
+    return null;
+}
+
+function removeFromQueue(id: string) {
+    const index = message_queue.findIndex(msg => msg.id === id);
+    if (index !== -1) {
+        message_queue.splice(index, 1);
+    }
+}
+
 export async function getMessages(limit: number = 100) {
     const response = await fetch(`/api/messages?limit=${limit}`);
     if (!response.ok) {
@@ -71,11 +280,11 @@ export async function getMessages(limit: number = 100) {
     }
     const data = await response.json();
     data.messages.forEach((msg: Message) => {
-        if (msg.published) {
+        if (msg.status === 'published') {
             if (!published_message_ids.has(msg.id)) {
                 published_messages.push(msg);
             }
-        } else {
+        } else if (msg.status === 'pending') {
             if (!message_queue_ids.has(msg.id)) {
                 message_queue.push(msg);
             }
@@ -116,21 +325,62 @@ export function getTwitchInfo(message: any): any | null {
 
 function handleMessage(event: MessageEvent) {
     console.log("Received message:", event.data);
-    const message: Message = JSON.parse(event.data);
+    const data = JSON.parse(event.data);
+
+    if (data.version !== PROTOCOL_VERSION) {
+        console.warn(`Unsupported protocol version ${data.version}, expected ${PROTOCOL_VERSION}`);
+    }
+    if (typeof data.seq === 'number') {
+        last_seq = data.seq;
+    }
+
+    if (data.type === 'snapshot') {
+        last_stream = data.stream;
+        message_queue.splice(0, message_queue.length, ...data.messages);
+        return;
+    }
+    if (data.type === 'listener_status') {
+        listener_statuses[`${data.platform}/${data.channel}`] = data;
+        return;
+    }
+    if (data.type === 'listener_stopped') {
+        delete listener_statuses[`${data.platform}/${data.channel}`];
+        return;
+    }
+    if (data.type === 'ack') {
+        return;
+    }
+    if (data.type === 'lagged') {
+        console.warn(`Admin socket missed ${data.missed} events, resyncing`);
+        return;
+    }
+    if (data.type === 'error') {
+        console.error("Admin command failed:", data.message);
+        return;
+    }
+    if (data.type !== 'message' && data.type !== 'message_updated') {
+        return;
+    }
+
+    const message: Message = data;
 
-    if (message.published) {
+    if (message.status === 'published') {
         if (!published_message_ids.has(message.id)) {
             published_messages.push(message);
         }
 
         if (message_queue_ids.has(message.id)) {
-            const index = message_queue.findIndex(msg => msg.id === message.id);
-            if (index !== -1) {
-                message_queue.splice(index, 1);
-            }
+            removeFromQueue(message.id);
         }
     
+    } else if (message.status !== 'pending') {
+        removeFromQueue(message.id);
     } else {
+        const published_index = published_messages.findIndex(msg => msg.id === message.id);
+        if (published_index !== -1) {
+            published_messages.splice(published_index, 1);
+        }
+
         if (!message_queue_ids.has(message.id)) {
             message_queue.push(message);
         }